@@ -0,0 +1,37 @@
+use calculator_rs::{Backend, Calculate, Calculator, CompiledExpression, Context};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+const FORMULA: &str = "age >= 18 and country = 1 ? price * qty * (1 - discount) : 0";
+
+fn context(qty: i64) -> Context {
+    Context::new()
+        .with("age", 30)
+        .with("country", 1)
+        .with("price", 12.5)
+        .with("qty", qty)
+        .with("discount", 0.1)
+}
+
+fn bench_calculate(c: &mut Criterion) {
+    let context = context(4);
+    let mut group = c.benchmark_group("formula");
+    group.bench_function("string", |b| {
+        b.iter(|| black_box(FORMULA).calculate_with(&context))
+    });
+    let expression = CompiledExpression::new(FORMULA).unwrap();
+    group.bench_function("compiled", |b| {
+        b.iter(|| expression.eval(black_box(&context)))
+    });
+    let expression = Calculator::builder()
+        .backend(Backend::Bytecode)
+        .build()
+        .compile(FORMULA)
+        .unwrap();
+    group.bench_function("bytecode", |b| {
+        b.iter(|| expression.eval(black_box(&context)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_calculate);
+criterion_main!(benches);
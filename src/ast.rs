@@ -1,36 +1,393 @@
-#[derive(Debug, PartialEq)]
+//! The parsed form of an expression, for inspecting or transforming it before
+//! evaluation. Expressions are parsed with [`parse`](crate::parse) or
+//! [`Calculator::parse`](crate::Calculator::parse), and evaluated with
+//! [`Calculator::evaluate`](crate::Calculator::evaluate).
+//!
+//! Expressions display as canonical source text, which parses back to the
+//! same expression:
+//!
+//! ```
+//! let expression = calculator_rs::parse("(1+1)*2+4!").unwrap();
+//! assert_eq!(expression.to_string(), "(1 + 1) * 2 + 4!");
+//! ```
+//!
+//! The enums are non-exhaustive, as later versions may add literals and
+//! operations.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::bigint::BigInt;
+use crate::decimal::Decimal;
+use crate::lexer::Token;
+use crate::parse::Fixity;
+use crate::span::Span;
+
+/// A value written in the input
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Literal {
+    Boolean(bool),
     Integer(i64),
+    /// An integer literal too large for an i64
+    BigInteger(BigInt),
+    /// A literal with a `d` suffix, or any fractional literal if decimal
+    /// literals are enabled
+    Decimal(Decimal),
     Float(f64),
+    /// A literal with an `i` suffix, the imaginary part of a complex number
+    Imaginary(f64),
 }
 
-#[derive(Debug, PartialEq)]
+/// An operator applied to its operands, in the order they are written
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Operation {
     Add(Box<Expression>, Box<Expression>),
+    /// A logical and, which only evaluates the right operand if the left one
+    /// is true
+    And(Box<Expression>, Box<Expression>),
     Assert(Box<Expression>),
+    BitwiseAnd(Box<Expression>, Box<Expression>),
+    BitwiseNot(Box<Expression>),
+    BitwiseOr(Box<Expression>, Box<Expression>),
+    BitwiseXor(Box<Expression>, Box<Expression>),
     Divide(Box<Expression>, Box<Expression>),
+    Equal(Box<Expression>, Box<Expression>),
     Exponentiate(Box<Expression>, Box<Expression>),
     Factorial(Box<Expression>),
+    GreaterThan(Box<Expression>, Box<Expression>),
+    GreaterThanOrEqual(Box<Expression>, Box<Expression>),
+    LessThan(Box<Expression>, Box<Expression>),
+    LessThanOrEqual(Box<Expression>, Box<Expression>),
     Modulo(Box<Expression>, Box<Expression>),
     Multiply(Box<Expression>, Box<Expression>),
     Negate(Box<Expression>),
+    Not(Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    /// A logical or, which only evaluates the right operand if the left one
+    /// is false
+    Or(Box<Expression>, Box<Expression>),
+    ShiftLeft(Box<Expression>, Box<Expression>),
+    ShiftRight(Box<Expression>, Box<Expression>),
     Subtract(Box<Expression>, Box<Expression>),
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Expression {
+/// An expression along with its location in the input.
+///
+/// Inputs of any depth can be calculated with [`Backend::Bytecode`] when the
+/// depth limit of [`EvalLimits`] is raised, but dropping, cloning, comparing
+/// and formatting an expression recurse into its operands, so deeply nested
+/// expressions returned by parsing rely on that limit for those.
+///
+/// [`Backend::Bytecode`]: crate::Backend::Bytecode
+/// [`EvalLimits`]: crate::EvalLimits
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new<K: Into<ExpressionKind>>(kind: K, span: Span) -> Expression {
+        Expression {
+            kind: kind.into(),
+            span,
+        }
+    }
+
+    /// Drops the operands from a stack rather than recursively, which would
+    /// overflow the call stack for deeply nested expressions, leaving a
+    /// literal in place of the expression
+    pub(crate) fn drop_operands(&mut self) {
+        let mut stack = Vec::new();
+        self.kind.take_operands(&mut stack);
+        while let Some(mut expression) = stack.pop() {
+            expression.kind.take_operands(&mut stack);
+        }
+    }
+}
+
+/// What an expression is, apart from its location
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ExpressionKind {
+    Call(String, Vec<Expression>),
+    /// A condition followed by the expressions to evaluate if it is true and
+    /// if it is false, of which only one is evaluated
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
     Literal(Literal),
     Operation(Operation),
+    Variable(String),
 }
 
-impl From<Literal> for Expression {
+impl Operation {
+    /// Returns the operands, from left to right
+    pub fn operands(&self) -> Vec<&Expression> {
+        match self {
+            Operation::Add(lhs, rhs)
+            | Operation::And(lhs, rhs)
+            | Operation::BitwiseAnd(lhs, rhs)
+            | Operation::BitwiseOr(lhs, rhs)
+            | Operation::BitwiseXor(lhs, rhs)
+            | Operation::Divide(lhs, rhs)
+            | Operation::Equal(lhs, rhs)
+            | Operation::Exponentiate(lhs, rhs)
+            | Operation::GreaterThan(lhs, rhs)
+            | Operation::GreaterThanOrEqual(lhs, rhs)
+            | Operation::LessThan(lhs, rhs)
+            | Operation::LessThanOrEqual(lhs, rhs)
+            | Operation::Modulo(lhs, rhs)
+            | Operation::Multiply(lhs, rhs)
+            | Operation::NotEqual(lhs, rhs)
+            | Operation::Or(lhs, rhs)
+            | Operation::ShiftLeft(lhs, rhs)
+            | Operation::ShiftRight(lhs, rhs)
+            | Operation::Subtract(lhs, rhs) => vec![lhs, rhs],
+            Operation::Assert(lhs)
+            | Operation::BitwiseNot(lhs)
+            | Operation::Factorial(lhs)
+            | Operation::Negate(lhs)
+            | Operation::Not(lhs) => vec![lhs],
+        }
+    }
+
+    /// Replaces each operand with the result of the function, calling it on
+    /// the operands from left to right
+    pub fn map_operands<F: FnMut(Expression) -> Expression>(self, mut f: F) -> Operation {
+        let mut f = |mut operand: Box<Expression>| {
+            *operand = f(*operand);
+            operand
+        };
+        match self {
+            Operation::Add(lhs, rhs) => Operation::Add(f(lhs), f(rhs)),
+            Operation::And(lhs, rhs) => Operation::And(f(lhs), f(rhs)),
+            Operation::Assert(lhs) => Operation::Assert(f(lhs)),
+            Operation::BitwiseAnd(lhs, rhs) => Operation::BitwiseAnd(f(lhs), f(rhs)),
+            Operation::BitwiseNot(lhs) => Operation::BitwiseNot(f(lhs)),
+            Operation::BitwiseOr(lhs, rhs) => Operation::BitwiseOr(f(lhs), f(rhs)),
+            Operation::BitwiseXor(lhs, rhs) => Operation::BitwiseXor(f(lhs), f(rhs)),
+            Operation::Divide(lhs, rhs) => Operation::Divide(f(lhs), f(rhs)),
+            Operation::Equal(lhs, rhs) => Operation::Equal(f(lhs), f(rhs)),
+            Operation::Exponentiate(lhs, rhs) => Operation::Exponentiate(f(lhs), f(rhs)),
+            Operation::Factorial(lhs) => Operation::Factorial(f(lhs)),
+            Operation::GreaterThan(lhs, rhs) => Operation::GreaterThan(f(lhs), f(rhs)),
+            Operation::GreaterThanOrEqual(lhs, rhs) => {
+                Operation::GreaterThanOrEqual(f(lhs), f(rhs))
+            }
+            Operation::LessThan(lhs, rhs) => Operation::LessThan(f(lhs), f(rhs)),
+            Operation::LessThanOrEqual(lhs, rhs) => Operation::LessThanOrEqual(f(lhs), f(rhs)),
+            Operation::Modulo(lhs, rhs) => Operation::Modulo(f(lhs), f(rhs)),
+            Operation::Multiply(lhs, rhs) => Operation::Multiply(f(lhs), f(rhs)),
+            Operation::Negate(lhs) => Operation::Negate(f(lhs)),
+            Operation::Not(lhs) => Operation::Not(f(lhs)),
+            Operation::NotEqual(lhs, rhs) => Operation::NotEqual(f(lhs), f(rhs)),
+            Operation::Or(lhs, rhs) => Operation::Or(f(lhs), f(rhs)),
+            Operation::ShiftLeft(lhs, rhs) => Operation::ShiftLeft(f(lhs), f(rhs)),
+            Operation::ShiftRight(lhs, rhs) => Operation::ShiftRight(f(lhs), f(rhs)),
+            Operation::Subtract(lhs, rhs) => Operation::Subtract(f(lhs), f(rhs)),
+        }
+    }
+
+    /// Returns the operands mutably, from left to right
+    pub fn operands_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Operation::Add(lhs, rhs)
+            | Operation::And(lhs, rhs)
+            | Operation::BitwiseAnd(lhs, rhs)
+            | Operation::BitwiseOr(lhs, rhs)
+            | Operation::BitwiseXor(lhs, rhs)
+            | Operation::Divide(lhs, rhs)
+            | Operation::Equal(lhs, rhs)
+            | Operation::Exponentiate(lhs, rhs)
+            | Operation::GreaterThan(lhs, rhs)
+            | Operation::GreaterThanOrEqual(lhs, rhs)
+            | Operation::LessThan(lhs, rhs)
+            | Operation::LessThanOrEqual(lhs, rhs)
+            | Operation::Modulo(lhs, rhs)
+            | Operation::Multiply(lhs, rhs)
+            | Operation::NotEqual(lhs, rhs)
+            | Operation::Or(lhs, rhs)
+            | Operation::ShiftLeft(lhs, rhs)
+            | Operation::ShiftRight(lhs, rhs)
+            | Operation::Subtract(lhs, rhs) => vec![lhs, rhs],
+            Operation::Assert(lhs)
+            | Operation::BitwiseNot(lhs)
+            | Operation::Factorial(lhs)
+            | Operation::Negate(lhs)
+            | Operation::Not(lhs) => vec![lhs],
+        }
+    }
+}
+
+impl ExpressionKind {
+    /// Returns the expressions this one is made of, from left to right
+    pub fn operands(&self) -> Vec<&Expression> {
+        match self {
+            ExpressionKind::Call(_, args) => args.iter().collect(),
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                vec![condition, then, otherwise]
+            }
+            ExpressionKind::Operation(operation) => operation.operands(),
+            ExpressionKind::Literal(_) | ExpressionKind::Variable(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the expressions this one is made of mutably, from left to right
+    pub fn operands_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            ExpressionKind::Call(_, args) => args.iter_mut().collect(),
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                vec![condition, then, otherwise]
+            }
+            ExpressionKind::Operation(operation) => operation.operands_mut(),
+            ExpressionKind::Literal(_) | ExpressionKind::Variable(_) => Vec::new(),
+        }
+    }
+
+    /// Moves the operands out into the vector, leaving a literal in place
+    fn take_operands(&mut self, operands: &mut Vec<Expression>) {
+        let kind = std::mem::replace(self, ExpressionKind::Literal(Literal::Integer(0)));
+        match kind {
+            ExpressionKind::Call(_, args) => operands.extend(args),
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                operands.extend([*condition, *then, *otherwise])
+            }
+            ExpressionKind::Operation(operation) => match operation {
+                Operation::Add(lhs, rhs)
+                | Operation::And(lhs, rhs)
+                | Operation::BitwiseAnd(lhs, rhs)
+                | Operation::BitwiseOr(lhs, rhs)
+                | Operation::BitwiseXor(lhs, rhs)
+                | Operation::Divide(lhs, rhs)
+                | Operation::Equal(lhs, rhs)
+                | Operation::Exponentiate(lhs, rhs)
+                | Operation::GreaterThan(lhs, rhs)
+                | Operation::GreaterThanOrEqual(lhs, rhs)
+                | Operation::LessThan(lhs, rhs)
+                | Operation::LessThanOrEqual(lhs, rhs)
+                | Operation::Modulo(lhs, rhs)
+                | Operation::Multiply(lhs, rhs)
+                | Operation::NotEqual(lhs, rhs)
+                | Operation::Or(lhs, rhs)
+                | Operation::ShiftLeft(lhs, rhs)
+                | Operation::ShiftRight(lhs, rhs)
+                | Operation::Subtract(lhs, rhs) => operands.extend([*lhs, *rhs]),
+                Operation::Assert(lhs)
+                | Operation::BitwiseNot(lhs)
+                | Operation::Factorial(lhs)
+                | Operation::Negate(lhs)
+                | Operation::Not(lhs) => operands.push(*lhs),
+            },
+            ExpressionKind::Literal(_) | ExpressionKind::Variable(_) => {}
+        }
+    }
+}
+
+impl From<Literal> for ExpressionKind {
     fn from(literal: Literal) -> Self {
-        Expression::Literal(literal)
+        ExpressionKind::Literal(literal)
     }
 }
 
-impl From<Operation> for Expression {
+impl From<Operation> for ExpressionKind {
     fn from(operation: Operation) -> Self {
-        Expression::Operation(operation)
+        ExpressionKind::Operation(operation)
+    }
+}
+
+/// Writes the literal as it would be written in the input
+impl Display for Literal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Boolean(b) => write!(f, "{}", b),
+            Literal::Integer(i) => write!(f, "{}", i),
+            Literal::BigInteger(i) => write!(f, "{}", i),
+            Literal::Decimal(d) => write!(f, "{}d", d),
+            // Debug formatting keeps the fraction of whole numbers, so they
+            // are parsed back as floats
+            Literal::Float(x) => write!(f, "{:?}", x),
+            Literal::Imaginary(x) => write!(f, "{:?}i", x),
+        }
+    }
+}
+
+/// Writes the expression as source text that parses back to it, with
+/// parentheses only where the precedence or associativity of its operators
+/// requires them
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let operands: Vec<_> = self
+            .operand_precs()
+            .into_iter()
+            .map(|(operand, min_prec)| Operand(operand, min_prec))
+            .collect();
+        match (&self.kind, Fixity::of(&self.kind)) {
+            (ExpressionKind::Call(name, _), _) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in operands.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            (ExpressionKind::Conditional(..), _) => {
+                write!(f, "{} ? {} : {}", operands[0], operands[1], operands[2])
+            }
+            (ExpressionKind::Literal(literal), _) => write!(f, "{}", literal),
+            (ExpressionKind::Operation(_), Some(Fixity::Prefix(token, _))) => {
+                // Keywords are separated from their operand
+                let space = if token == Token::Not { " " } else { "" };
+                write!(f, "{}{}{}", token, space, operands[0])
+            }
+            (ExpressionKind::Operation(_), Some(Fixity::Infix(token, ..))) => {
+                write!(f, "{} {} {}", operands[0], token, operands[1])
+            }
+            (ExpressionKind::Operation(_), Some(Fixity::Postfix(token, _))) => {
+                write!(f, "{}{}", operands[0], token)
+            }
+            (ExpressionKind::Variable(name), _) => f.write_str(name),
+            _ => unreachable!("operations have a fixity"),
+        }
+    }
+}
+
+impl Expression {
+    /// Returns the precedence of the expression's operator, which is the
+    /// highest for expressions without one
+    pub(crate) fn prec(&self) -> u8 {
+        Fixity::of(&self.kind).map_or(u8::MAX, |fixity| fixity.prec())
+    }
+
+    /// Returns the operands along with the lowest precedence each may have
+    /// without being parenthesized where it is written
+    pub(crate) fn operand_precs(&self) -> Vec<(&Expression, u8)> {
+        let operands = self.kind.operands();
+        let min_precs = match (&self.kind, Fixity::of(&self.kind)) {
+            // The expression between `?` and `:` is delimited by them
+            (ExpressionKind::Conditional(..), Some(Fixity::Infix(_, prec, assoc))) => {
+                vec![prec + 1 - assoc, 0, prec + assoc]
+            }
+            (_, Some(Fixity::Infix(_, prec, assoc))) => vec![prec + 1 - assoc, prec + assoc],
+            (_, Some(Fixity::Prefix(_, prec) | Fixity::Postfix(_, prec))) => vec![prec],
+            (_, None) => vec![0; operands.len()],
+        };
+        operands.into_iter().zip(min_precs).collect()
+    }
+}
+
+/// An operand, displayed in parentheses if its operator binds more loosely
+/// than the position it is written in allows
+struct Operand<'a>(&'a Expression, u8);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Operand(operand, min_prec) = self;
+        if operand.prec() < *min_prec {
+            write!(f, "({})", operand)
+        } else {
+            write!(f, "{}", operand)
+        }
     }
 }
@@ -0,0 +1,670 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Not, Shl, Shr, Sub};
+use std::str::FromStr;
+
+/// An arbitrary-precision signed integer.
+///
+/// The magnitude is stored as little-endian base 2^32 limbs without trailing
+/// zeros, so zero is an empty magnitude and is never negative.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn zero() -> BigInt {
+        BigInt::default()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt {
+            negative: false,
+            magnitude: self.magnitude.clone(),
+        }
+    }
+
+    /// Returns the number of bits in the magnitude, which is 0 for zero
+    pub fn bits(&self) -> u64 {
+        match self.magnitude.last() {
+            Some(last) => self.magnitude.len() as u64 * 32 - last.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    /// Returns the value as an i64, if it fits
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    /// Returns the nearest float, which is infinite if the value is out of range
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0f64, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// Raises the value to the given power
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut base = self.clone();
+        let mut result = BigInt::from(1i64);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    /// Formats the integer in the given radix (2 to 36), with lowercase digits
+    pub fn to_str_radix(&self, radix: u32) -> String {
+        if self.is_zero() {
+            return "0".into();
+        }
+        let mut digits = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = div_rem_limb(&magnitude, radix);
+            digits.push(std::char::from_digit(remainder, radix).unwrap_or_else(|| unreachable!()));
+            magnitude = BigInt::from_parts(false, quotient).magnitude;
+        }
+        if self.negative {
+            digits.push('-');
+        }
+        digits.iter().rev().collect()
+    }
+
+    /// Returns the two's complement limbs, sign extended to len limbs
+    fn twos_complement(&self, len: usize) -> Vec<u32> {
+        let mut limbs = self.magnitude.clone();
+        limbs.resize(len, 0);
+        if self.negative {
+            negate_limbs(&mut limbs);
+        }
+        limbs
+    }
+
+    /// Applies the operation to the two's complement limbs of both integers
+    fn bitwise(&self, other: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+        let len = self.magnitude.len().max(other.magnitude.len()) + 1;
+        let a = self.twos_complement(len);
+        let b = other.twos_complement(len);
+        BigInt::from_twos_complement(a.into_iter().zip(b).map(|(a, b)| op(a, b)).collect())
+    }
+
+    fn from_twos_complement(mut limbs: Vec<u32>) -> BigInt {
+        let negative = limbs.last().is_some_and(|limb| limb >> 31 == 1);
+        if negative {
+            negate_limbs(&mut limbs);
+        }
+        BigInt::from_parts(negative, limbs)
+    }
+
+    /// Parses unsigned digits in the given radix (2 to 36), or returns None if
+    /// there are none or any is out of range
+    pub fn from_str_radix(digits: &str, radix: u32) -> Option<BigInt> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut magnitude = Vec::new();
+        for c in digits.chars() {
+            mul_add_limb(&mut magnitude, radix, c.to_digit(radix)?);
+        }
+        Some(BigInt::from_parts(false, magnitude))
+    }
+
+    /// Divides by the given value, truncating towards zero like the primitive
+    /// integer types. The remainder has the sign of the dividend. Returns None
+    /// when dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = match other.magnitude.as_slice() {
+            [divisor] => {
+                let (quotient, remainder) = div_rem_limb(&self.magnitude, *divisor);
+                (quotient, vec![remainder])
+            }
+            _ => div_rem_magnitude(&self.magnitude, &other.magnitude),
+        };
+        Some((
+            BigInt::from_parts(self.negative != other.negative, quotient),
+            BigInt::from_parts(self.negative, remainder),
+        ))
+    }
+
+    fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude,
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(i: i64) -> Self {
+        let mut big = BigInt::from(i.unsigned_abs());
+        big.negative = i < 0;
+        big
+    }
+}
+
+impl From<u64> for BigInt {
+    fn from(u: u64) -> Self {
+        BigInt::from_parts(false, vec![u as u32, (u >> 32) as u32])
+    }
+}
+
+/// Parses a string of decimal digits with an optional leading sign
+impl FromStr for BigInt {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let mut magnitude = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let value = chunk
+                .iter()
+                .fold(0u32, |acc, b| acc * 10 + (b - b'0') as u32);
+            mul_add_limb(&mut magnitude, 10u32.pow(chunk.len() as u32), value);
+        }
+        Ok(BigInt::from_parts(negative, magnitude))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+        let mut chunks = Vec::new();
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (quotient, remainder) = div_rem_limb(&magnitude, 1_000_000_000);
+            chunks.push(remainder);
+            magnitude = BigInt::from_parts(false, quotient).magnitude;
+        }
+        let mut s = String::new();
+        if self.negative {
+            s.push('-');
+        }
+        s.push_str(&chunks.pop().unwrap_or(0).to_string());
+        for chunk in chunks.iter().rev() {
+            s.push_str(&format!("{:09}", chunk));
+        }
+        f.write_str(&s)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.magnitude)
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(
+                self.negative,
+                add_magnitude(&self.magnitude, &other.magnitude),
+            );
+        }
+        match cmp_magnitude(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt::from_parts(
+                other.negative,
+                sub_magnitude(&other.magnitude, &self.magnitude),
+            ),
+            _ => BigInt::from_parts(
+                self.negative,
+                sub_magnitude(&self.magnitude, &other.magnitude),
+            ),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other.clone()
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut magnitude = vec![0u32; self.magnitude.len() + other.magnitude.len()];
+        for (i, &a) in self.magnitude.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in other.magnitude.iter().enumerate() {
+                let product = a as u64 * b as u64 + magnitude[i + j] as u64 + carry;
+                magnitude[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            magnitude[i + other.magnitude.len()] = carry as u32;
+        }
+        BigInt::from_parts(self.negative != other.negative, magnitude)
+    }
+}
+
+/// Bitwise operators act on the infinite two's complement representation, like
+/// the primitive integer types
+macro_rules! bitwise_binop {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait for &BigInt {
+            type Output = BigInt;
+
+            fn $method(self, other: &BigInt) -> BigInt {
+                self.bitwise(other, |a, b| a $op b)
+            }
+        }
+    };
+}
+
+bitwise_binop!(BitAnd, bitand, &);
+bitwise_binop!(BitOr, bitor, |);
+bitwise_binop!(BitXor, bitxor, ^);
+
+impl Not for BigInt {
+    type Output = BigInt;
+
+    fn not(self) -> BigInt {
+        -self - BigInt::from(1i64)
+    }
+}
+
+impl Shl<u32> for &BigInt {
+    type Output = BigInt;
+
+    fn shl(self, bits: u32) -> BigInt {
+        let mut magnitude = vec![0u32; (bits / 32) as usize];
+        let bits = bits % 32;
+        let mut carry = 0u32;
+        for &limb in &self.magnitude {
+            if bits == 0 {
+                magnitude.push(limb);
+            } else {
+                magnitude.push(limb << bits | carry);
+                carry = limb >> (32 - bits);
+            }
+        }
+        magnitude.push(carry);
+        BigInt::from_parts(self.negative, magnitude)
+    }
+}
+
+/// Shifts right arithmetically, rounding towards negative infinity
+impl Shr<u32> for &BigInt {
+    type Output = BigInt;
+
+    fn shr(self, bits: u32) -> BigInt {
+        if self.negative {
+            return !(&!self.clone() >> bits);
+        }
+        let limbs = self
+            .magnitude
+            .get((bits / 32) as usize..)
+            .unwrap_or_default();
+        let bits = bits % 32;
+        let magnitude = (0..limbs.len())
+            .map(|i| match bits {
+                0 => limbs[i],
+                _ => limbs[i] >> bits | limbs.get(i + 1).map_or(0, |hi| hi << (32 - bits)),
+            })
+            .collect();
+        BigInt::from_parts(false, magnitude)
+    }
+}
+
+macro_rules! forward_binop {
+    ($trait:ident, $method:ident) => {
+        impl $trait for BigInt {
+            type Output = BigInt;
+
+            fn $method(self, other: BigInt) -> BigInt {
+                (&self).$method(&other)
+            }
+        }
+    };
+}
+
+forward_binop!(Add, add);
+forward_binop!(Sub, sub);
+forward_binop!(Mul, mul);
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    result.push(carry as u32);
+    result
+}
+
+/// Subtracts b from a, which must not be smaller than b
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut diff = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        result.push(diff as u32);
+    }
+    result
+}
+
+/// Negates two's complement limbs in place, by inverting and adding one
+fn negate_limbs(limbs: &mut [u32]) {
+    let mut carry = true;
+    for limb in limbs.iter_mut() {
+        (*limb, carry) = (!*limb).overflowing_add(carry as u32);
+    }
+}
+
+/// Multiplies the magnitude by a limb and adds another limb to it, in place
+fn mul_add_limb(magnitude: &mut Vec<u32>, mul: u32, add: u32) {
+    let mut carry = add as u64;
+    for limb in magnitude.iter_mut() {
+        let product = *limb as u64 * mul as u64 + carry;
+        *limb = product as u32;
+        carry = product >> 32;
+    }
+    if carry > 0 {
+        magnitude.push(carry as u32);
+    }
+}
+
+fn div_rem_limb(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for (i, &limb) in a.iter().enumerate().rev() {
+        let current = (remainder << 32) | limb as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    (quotient, remainder as u32)
+}
+
+/// Long division a limb of the quotient at a time, with Knuth's algorithm D
+/// (The Art of Computer Programming, vol. 2, 4.3.1). The divisor has at least
+/// two limbs and no trailing zeros.
+fn div_rem_magnitude(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_magnitude(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    // Shift both so the divisor's top bit is set, which makes each estimated
+    // limb of the quotient at most two too large
+    let shift = b[b.len() - 1].leading_zeros();
+    let n = b.len();
+    let mut b = shl_limbs(b, shift);
+    b.truncate(n);
+    let mut u = shl_limbs(a, shift);
+    let mut quotient = vec![0u32; a.len() - n + 1];
+    for j in (0..quotient.len()).rev() {
+        // Estimate the quotient limb from the top two limbs of the remainder
+        // and the top limb of the divisor, then correct it with the next
+        let top = (u[j + n] as u64) << 32 | u[j + n - 1] as u64;
+        let mut q = top / b[n - 1] as u64;
+        let mut r = top % b[n - 1] as u64;
+        while q >> 32 != 0 || q * b[n - 2] as u64 > (r << 32 | u[j + n - 2] as u64) {
+            q -= 1;
+            r += b[n - 1] as u64;
+            if r >> 32 != 0 {
+                break;
+            }
+        }
+        // Subtract q times the divisor, adding it back if q was one too large
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = q * b[i] as u64 + carry;
+            carry = product >> 32;
+            let diff = u[i + j] as i64 - borrow - (product as u32) as i64;
+            u[i + j] = diff as u32;
+            borrow = (diff < 0) as i64;
+        }
+        let diff = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = diff as u32;
+        if diff < 0 {
+            q -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + b[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = q as u32;
+    }
+    // The remainder is what is left of the dividend, shifted back
+    let remainder = (0..n)
+        .map(|i| match shift {
+            0 => u[i],
+            _ => u[i] >> shift | u[i + 1] << (32 - shift),
+        })
+        .collect();
+    (quotient, remainder)
+}
+
+/// Shifts the limbs left by less than a limb, with a limb for the carry
+fn shl_limbs(limbs: &[u32], shift: u32) -> Vec<u32> {
+    let mut shifted = Vec::with_capacity(limbs.len() + 1);
+    let mut carry = 0;
+    for &limb in limbs {
+        shifted.push(limb << shift | carry);
+        carry = if shift == 0 { 0 } else { limb >> (32 - shift) };
+    }
+    shifted.push(carry);
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_bigint() {
+        assert_eq!(big("0").to_string(), "0");
+        assert_eq!(big("0").bits(), 0);
+        assert_eq!(big("-255").bits(), 8);
+        assert_eq!(big("18446744073709551616").bits(), 65);
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(
+            big("-123456789012345678901234567890").to_string(),
+            "-123456789012345678901234567890"
+        );
+        assert_eq!(BigInt::from(i64::MIN).to_string(), i64::MIN.to_string());
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from(i64::MAX).to_i64(), Some(i64::MAX));
+        assert_eq!((BigInt::from(i64::MAX) + BigInt::from(1i64)).to_i64(), None);
+        assert_eq!(
+            (BigInt::from(i64::MAX) + BigInt::from(1i64)).to_string(),
+            "9223372036854775808"
+        );
+        assert_eq!(big("-5") + big("3"), big("-2"));
+        assert_eq!(big("5") - big("8"), big("-3"));
+        assert_eq!(big("-5") - big("-5"), BigInt::zero());
+        assert_eq!(
+            BigInt::from(2i64).pow(200).to_string(),
+            "1606938044258990275541962092341162602522202993782792835301376"
+        );
+        assert_eq!(big("-3").pow(3), big("-27"));
+        assert_eq!(
+            big("-12345678901234567890123") * big("98765432109876543210"),
+            big("-1219326311370217952249611949260778341714830")
+        );
+        assert_eq!(BigInt::from(2i64).pow(70).to_f64(), 2f64.powi(70));
+        assert!(big("-3") < big("2"));
+        assert!(big("-30000000000000000000") < big("-3"));
+        assert!("1a".parse::<BigInt>().is_err());
+        assert_eq!(
+            BigInt::from_str_radix("ffffffffffffffffff", 16),
+            Some(big("4722366482869645213695"))
+        );
+        assert_eq!(BigInt::from_str_radix("102", 2), None);
+        assert_eq!(
+            big("-4722366482869645213695").to_str_radix(16),
+            "-ffffffffffffffffff"
+        );
+        assert_eq!(big("5").to_str_radix(2), "101");
+        assert_eq!(BigInt::zero().to_str_radix(8), "0");
+    }
+
+    #[test]
+    fn test_bigint_bitwise() {
+        let pairs = [(12i64, 10i64), (-12, 10), (12, -10), (-12, -10), (0, -1)];
+        for (a, b) in pairs {
+            let (x, y) = (BigInt::from(a), BigInt::from(b));
+            assert_eq!(&x & &y, BigInt::from(a & b), "{} & {}", a, b);
+            assert_eq!(&x | &y, BigInt::from(a | b), "{} | {}", a, b);
+            assert_eq!(&x ^ &y, BigInt::from(a ^ b), "{} ^ {}", a, b);
+            assert_eq!(!x.clone(), BigInt::from(!a));
+            for bits in [0, 1, 3, 40] {
+                assert_eq!(&x >> bits, BigInt::from(a >> bits), "{} >> {}", a, bits);
+            }
+        }
+        let mask = big("18446744073709551615");
+        assert_eq!(&big("-1") & &mask, mask);
+        assert_eq!(&mask ^ &mask, BigInt::zero());
+        assert_eq!(&big("-3") << 64, big("-55340232221128654848"));
+        assert_eq!(&big("1") << 100, BigInt::from(2i64).pow(100));
+        assert_eq!(&BigInt::from(2i64).pow(100) >> 98, big("4"));
+        assert_eq!(&big("-1") >> 1000, big("-1"));
+        assert!("".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn test_bigint_div_rem() {
+        let a = big("1219326311370217952249611949260778341714831");
+        let b = big("98765432109876543210");
+        assert_eq!(
+            a.div_rem(&b),
+            Some((big("12345678901234567890123"), big("1")))
+        );
+        assert_eq!(
+            (-a.clone()).div_rem(&b),
+            Some((big("-12345678901234567890123"), big("-1")))
+        );
+        assert_eq!(big("-7").div_rem(&big("2")), Some((big("-3"), big("-1"))));
+        assert_eq!(big("7").div_rem(&big("-2")), Some((big("-3"), big("1"))));
+        assert_eq!(b.div_rem(&a), Some((BigInt::zero(), b.clone())));
+        assert_eq!(a.div_rem(&BigInt::zero()), None);
+
+        // Divisors whose quotient limbs are estimated too large, and large
+        // operands checked against multiplication
+        let cases = [
+            (
+                "340282366920938463463374607431768211455",
+                "18446744073709551617",
+            ),
+            (
+                "340282366920938463444927863358058659840",
+                "18446744073709551615",
+            ),
+            (
+                "79228162514264337589248983040",
+                "79228162514264337589248983041",
+            ),
+            (
+                "6277101735386680763835789423207666416102355444464034512895",
+                "4294967297",
+            ),
+        ];
+        for (a, b) in cases {
+            let (a, b) = (big(a), big(b));
+            let (quotient, remainder) = a.div_rem(&b).unwrap();
+            assert_eq!(&(&quotient * &b) + &remainder, a, "{} / {}", a, b);
+            assert!(remainder.abs() < b.abs());
+        }
+        let a = BigInt::from(3i64).pow(6000);
+        let b = &BigInt::from(2i64).pow(6000) + &big("1");
+        let (quotient, remainder) = a.div_rem(&b).unwrap();
+        assert_eq!(&(&quotient * &b) + &remainder, a);
+        assert!(remainder < b);
+        let (quotient, remainder) = b.pow(3).div_rem(&b).unwrap();
+        assert_eq!((quotient, remainder), (b.pow(2), BigInt::zero()));
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A complex number with floating point parts
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    /// Creates a complex number from its magnitude and angle in radians
+    pub fn from_polar(norm: f64, arg: f64) -> Complex {
+        Complex::new(norm * arg.cos(), norm * arg.sin())
+    }
+
+    /// Returns the magnitude
+    pub fn norm(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    /// Returns the angle in radians, between -π and π
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn conj(&self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn exp(&self) -> Complex {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    /// Returns the principal natural logarithm
+    pub fn ln(&self) -> Complex {
+        Complex::new(self.norm().ln(), self.arg())
+    }
+
+    /// Returns the principal square root, which has a non-negative real part
+    pub fn sqrt(&self) -> Complex {
+        let norm = self.norm();
+        Complex::new(
+            ((norm + self.re) / 2.0).sqrt(),
+            ((norm - self.re) / 2.0).sqrt().copysign(self.im),
+        )
+    }
+
+    /// Raises to an integer power by repeated multiplication, which keeps
+    /// results like i^2 exact
+    pub fn powi(&self, exponent: i32) -> Complex {
+        let mut base = *self;
+        let mut result = Complex::new(1.0, 0.0);
+        let mut n = exponent.unsigned_abs();
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        if exponent < 0 {
+            Complex::new(1.0, 0.0) / result
+        } else {
+            result
+        }
+    }
+
+    /// Returns the principal value of raising to a complex power
+    pub fn powc(&self, exponent: Complex) -> Complex {
+        if exponent.im == 0.0 && exponent.re.fract() == 0.0 && exponent.re.abs() <= 1024.0 {
+            return self.powi(exponent.re as i32);
+        }
+        if self.re == 0.0 && self.im == 0.0 {
+            return Complex::default();
+        }
+        (exponent * self.ln()).exp()
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Self {
+        Complex::new(re, 0.0)
+    }
+}
+
+/// Displays the number in rectangular form like `3+4i`, or with the alternate
+/// flag in polar form like `5∠0.9273` (magnitude and angle in radians). A
+/// precision applies to both parts.
+impl Display for Complex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let part = |x: f64| match f.precision() {
+            Some(digits) => format!("{:.*}", digits, x),
+            None => x.to_string(),
+        };
+        if f.alternate() {
+            write!(f, "{}∠{}", part(self.norm()), part(self.arg()))
+        } else {
+            // NaN is written without a sign, even when its sign bit is set
+            let sign = if self.im.is_sign_negative() && !self.im.is_nan() {
+                ""
+            } else {
+                "+"
+            };
+            write!(f, "{}{}{}i", part(self.re), sign, part(self.im))
+        }
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// Divides like floats do, giving infinite or NaN parts when dividing by zero
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, other: Complex) -> Complex {
+        let denom = other.re * other.re + other.im * other.im;
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / denom,
+            (self.im * other.re - self.re * other.im) / denom,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complex() {
+        let i = Complex::new(0.0, 1.0);
+        assert_eq!(i * i, Complex::from(-1.0));
+        assert_eq!(i.powi(2), Complex::from(-1.0));
+        assert_eq!(i.powi(-1), Complex::new(0.0, -1.0));
+        assert_eq!(Complex::new(3.0, 4.0).norm(), 5.0);
+        assert_eq!(Complex::from(-4.0).sqrt(), Complex::new(0.0, 2.0));
+        assert_eq!(Complex::new(-4.0, -0.0).sqrt(), Complex::new(0.0, -2.0));
+        assert_eq!(
+            Complex::new(1.0, 2.0) / Complex::new(3.0, 4.0),
+            Complex::new(0.44, 0.08)
+        );
+        let root = Complex::from(-8.0).powc(Complex::from(1.0 / 3.0));
+        assert!((root.re - 1.0).abs() < 1e-12);
+        assert!((root.im - 3f64.sqrt()).abs() < 1e-12);
+        assert_eq!(Complex::new(3.0, 4.0).to_string(), "3+4i");
+        assert_eq!(Complex::new(3.0, -4.0).to_string(), "3-4i");
+        assert_eq!(Complex::new(f64::NAN, -f64::NAN).to_string(), "NaN+NaNi");
+        assert_eq!(Complex::new(1.0, f64::NEG_INFINITY).to_string(), "1-infi");
+        assert_eq!(format!("{:.2}", Complex::new(0.5, 1.0 / 3.0)), "0.50+0.33i");
+        assert_eq!(format!("{:#.3}", Complex::new(3.0, 4.0)), "5.000∠0.927");
+    }
+}
@@ -0,0 +1,33 @@
+use crate::Value;
+use std::collections::HashMap;
+
+/// A set of named values that expressions can refer to as variables.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    variables: HashMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// Binds a variable, returning the value it was previously bound to, if any
+    pub fn set<N: Into<String>, V: Into<Value>>(&mut self, name: N, value: V) -> Option<Value> {
+        self.variables.insert(name.into(), value.into())
+    }
+
+    /// Binds a variable, consuming and returning the context to allow chaining
+    pub fn with<N: Into<String>, V: Into<Value>>(mut self, name: N, value: V) -> Context {
+        self.set(name, value);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.variables.remove(name)
+    }
+}
@@ -0,0 +1,359 @@
+use crate::bigint::BigInt;
+use crate::rational::Rational;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// How a decimal is rounded when digits have to be dropped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RoundingMode {
+    /// Away from zero
+    Up,
+    /// Towards zero, i.e. truncating
+    Down,
+    /// Towards positive infinity
+    Ceiling,
+    /// Towards negative infinity
+    Floor,
+    /// To the nearest neighbour, or away from zero if both are equally near
+    HalfUp,
+    /// To the nearest neighbour, or towards zero if both are equally near
+    HalfDown,
+    /// To the nearest neighbour, or the even one if both are equally near
+    #[default]
+    HalfEven,
+}
+
+/// Options for decimal arithmetic, set through the calculator builder
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DecimalOptions {
+    /// The number of fractional digits products, quotients and powers are
+    /// rounded to
+    pub(crate) scale: u32,
+    pub(crate) rounding: RoundingMode,
+    /// Whether number literals with a fractional part are parsed as decimals
+    pub(crate) literals: bool,
+}
+
+impl Default for DecimalOptions {
+    fn default() -> Self {
+        DecimalOptions {
+            scale: 2,
+            rounding: RoundingMode::default(),
+            literals: false,
+        }
+    }
+}
+
+/// A fixed-point decimal number: an integer mantissa scaled down by a power of
+/// ten, e.g. 12.50 is stored as 1250 with a scale of 2.
+///
+/// The scale is kept as written, so 12.50 displays with two fractional digits
+/// but compares equal to 12.5. Like rationals, the parts are kept on the heap.
+#[derive(Clone, Debug)]
+pub struct Decimal(Box<Parts>);
+
+#[derive(Clone, Debug)]
+struct Parts {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: BigInt, scale: u32) -> Decimal {
+        Decimal(Box::new(Parts { mantissa, scale }))
+    }
+
+    /// Rounds the rational to the given number of fractional digits
+    pub fn from_rational(r: &Rational, scale: u32, rounding: RoundingMode) -> Decimal {
+        let numer = r.numer() * &pow10(scale);
+        Decimal::new(round_div(&numer, r.denom(), rounding), scale)
+    }
+
+    pub fn mantissa(&self) -> &BigInt {
+        &self.0.mantissa
+    }
+
+    /// Returns the number of fractional digits
+    pub fn scale(&self) -> u32 {
+        self.0.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.mantissa.is_zero()
+    }
+
+    pub fn abs(&self) -> Decimal {
+        Decimal::new(self.0.mantissa.abs(), self.0.scale)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Parsing the decimal representation rounds correctly, unlike dividing
+        // two floats which may each be inexact.
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    pub fn to_rational(&self) -> Rational {
+        Rational::new(self.0.mantissa.clone(), pow10(self.0.scale))
+            .unwrap_or_else(|| unreachable!())
+    }
+
+    /// Rounds to at most the given number of fractional digits. Decimals with
+    /// fewer digits are returned unchanged.
+    pub fn round(&self, scale: u32, rounding: RoundingMode) -> Decimal {
+        if scale >= self.0.scale {
+            return self.clone();
+        }
+        let divisor = pow10(self.0.scale - scale);
+        Decimal::new(round_div(&self.0.mantissa, &divisor, rounding), scale)
+    }
+
+    /// Raises the decimal to a non-negative power, exactly
+    pub fn pow(&self, exponent: u32) -> Option<Decimal> {
+        let scale = self.0.scale.checked_mul(exponent)?;
+        Some(Decimal::new(self.0.mantissa.pow(exponent), scale))
+    }
+
+    /// Returns the remainder of truncating division, which has the sign of the
+    /// dividend, or None if other is zero
+    pub fn rem(&self, other: &Decimal) -> Option<Decimal> {
+        let scale = self.0.scale.max(other.0.scale);
+        let (_, rem) = self.rescale(scale).div_rem(&other.rescale(scale))?;
+        Some(Decimal::new(rem, scale))
+    }
+
+    /// Returns the mantissa for a scale at least as large as the decimal's
+    fn rescale(&self, scale: u32) -> BigInt {
+        &self.0.mantissa * &pow10(scale - self.0.scale)
+    }
+}
+
+impl From<BigInt> for Decimal {
+    fn from(i: BigInt) -> Self {
+        Decimal::new(i, 0)
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Decimal::from(BigInt::from(i))
+    }
+}
+
+/// Parses digits with an optional fractional part, exponent and leading sign,
+/// keeping trailing zeros as scale, e.g. `12.50` or `1.5e3`
+impl FromStr for Decimal {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, exponent) = match s.split_once(['e', 'E']) {
+            Some((s, exponent)) => (s, exponent.parse::<i64>().map_err(|_| ())?),
+            None => (s, 0),
+        };
+        let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+        if !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+        let mantissa: BigInt = format!("{}{}", int, frac).parse()?;
+        let scale = i64::try_from(frac.len()).map_err(|_| ())? - exponent;
+        match u32::try_from(scale) {
+            Ok(scale) => Ok(Decimal::new(mantissa, scale)),
+            Err(_) if scale < 0 => {
+                let shift = u32::try_from(-scale).map_err(|_| ())?;
+                Ok(Decimal::new(&mantissa * &pow10(shift), 0))
+            }
+            Err(_) => Err(()),
+        }
+    }
+}
+
+/// Displays all fractional digits of the scale, or rounds half away from zero
+/// to the given precision, e.g. `{:.1}` displays 12.25 as `12.3`.
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let decimal = match f.precision() {
+            Some(digits) => {
+                let digits = u32::try_from(digits).unwrap_or(u32::MAX);
+                let rounded = self.round(digits, RoundingMode::HalfUp);
+                Decimal::new(rounded.rescale(digits), digits)
+            }
+            None => self.clone(),
+        };
+        let digits = decimal.0.scale as usize;
+        let mut s = decimal.0.mantissa.abs().to_string();
+        if s.len() <= digits {
+            s = "0".repeat(digits - s.len() + 1) + &s;
+        }
+        if digits > 0 {
+            s.insert(s.len() - digits, '.');
+        }
+        if decimal.0.mantissa.is_negative() {
+            s.insert(0, '-');
+        }
+        f.write_str(&s)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.0.scale.max(other.0.scale);
+        self.rescale(scale).cmp(&other.rescale(scale))
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal::new(-self.0.mantissa, self.0.scale)
+    }
+}
+
+/// Adds exactly, with the larger of the two scales
+impl Add for &Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: &Decimal) -> Decimal {
+        let scale = self.0.scale.max(other.0.scale);
+        Decimal::new(self.rescale(scale) + other.rescale(scale), scale)
+    }
+}
+
+/// Subtracts exactly, with the larger of the two scales
+impl Sub for &Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: &Decimal) -> Decimal {
+        let scale = self.0.scale.max(other.0.scale);
+        Decimal::new(self.rescale(scale) - other.rescale(scale), scale)
+    }
+}
+
+/// Multiplies exactly, with the sum of the two scales
+impl Mul for &Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: &Decimal) -> Decimal {
+        Decimal::new(
+            &self.0.mantissa * &other.0.mantissa,
+            self.0.scale + other.0.scale,
+        )
+    }
+}
+
+fn pow10(exponent: u32) -> BigInt {
+    BigInt::from(10i64).pow(exponent)
+}
+
+/// Divides by a positive divisor, rounding the quotient with the given mode
+fn round_div(dividend: &BigInt, divisor: &BigInt, rounding: RoundingMode) -> BigInt {
+    let (quot, rem) = dividend.div_rem(divisor).unwrap_or_else(|| unreachable!());
+    if rem.is_zero() {
+        return quot;
+    }
+    let negative = dividend.is_negative();
+    let half = (&rem.abs() * &BigInt::from(2i64)).cmp(divisor);
+    let away = match rounding {
+        RoundingMode::Up => true,
+        RoundingMode::Down => false,
+        RoundingMode::Ceiling => !negative,
+        RoundingMode::Floor => negative,
+        RoundingMode::HalfUp => half != Ordering::Less,
+        RoundingMode::HalfDown => half == Ordering::Greater,
+        RoundingMode::HalfEven => match half {
+            Ordering::Equal => quot
+                .div_rem(&BigInt::from(2i64))
+                .is_some_and(|(_, rem)| !rem.is_zero()),
+            ordering => ordering == Ordering::Greater,
+        },
+    };
+    match (away, negative) {
+        (false, _) => quot,
+        (true, false) => quot + BigInt::from(1i64),
+        (true, true) => quot - BigInt::from(1i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(decimal("12.50").to_string(), "12.50");
+        assert_eq!(decimal("-0.05").to_string(), "-0.05");
+        assert_eq!(decimal("12.50"), decimal("12.5"));
+        assert!(decimal("0.1") < decimal("0.12"));
+        assert!("1.2.3".parse::<Decimal>().is_err());
+        assert!("1.".parse::<Decimal>().is_ok());
+        assert_eq!(decimal(".5").to_string(), "0.5");
+        assert_eq!(decimal("1.5e3").to_string(), "1500");
+        assert_eq!(decimal("15E-4").to_string(), "0.0015");
+        assert_eq!((&decimal("0.1") + &decimal("0.2")).to_string(), "0.3");
+        assert_eq!((&decimal("1.00") - &decimal("0.125")).to_string(), "0.875");
+        assert_eq!((&decimal("12.50") * &decimal("3")).to_string(), "37.50");
+        assert_eq!(decimal("7.5").rem(&decimal("2")), Some(decimal("1.5")));
+        assert_eq!(decimal("7.5").rem(&decimal("0.0")), None);
+        assert_eq!(decimal("1.5").pow(2), Some(decimal("2.25")));
+        assert_eq!(format!("{:.1}", decimal("12.25")), "12.3");
+        assert_eq!(format!("{:.3}", decimal("1.5")), "1.500");
+        assert_eq!(decimal("0.1").to_f64(), 0.1);
+    }
+
+    #[test]
+    fn test_rounding_mode() {
+        let round = |s: &str, rounding| decimal(s).round(0, rounding).to_string();
+        let cases = [
+            // Up, Down, Ceiling, Floor, HalfUp, HalfDown, HalfEven
+            ("2.5", ["3", "2", "3", "2", "3", "2", "2"]),
+            ("3.5", ["4", "3", "4", "3", "4", "3", "4"]),
+            ("2.6", ["3", "2", "3", "2", "3", "3", "3"]),
+            ("-2.5", ["-3", "-2", "-2", "-3", "-3", "-2", "-2"]),
+            ("-2.4", ["-3", "-2", "-2", "-3", "-2", "-2", "-2"]),
+            ("2.0", ["2", "2", "2", "2", "2", "2", "2"]),
+        ];
+        let modes = [
+            RoundingMode::Up,
+            RoundingMode::Down,
+            RoundingMode::Ceiling,
+            RoundingMode::Floor,
+            RoundingMode::HalfUp,
+            RoundingMode::HalfDown,
+            RoundingMode::HalfEven,
+        ];
+        for (input, expected) in cases {
+            for (mode, expected) in modes.iter().zip(expected) {
+                assert_eq!(round(input, *mode), expected, "{} {:?}", input, mode);
+            }
+        }
+        let third = Rational::new(BigInt::from(1i64), BigInt::from(3i64)).unwrap();
+        assert_eq!(
+            Decimal::from_rational(&third, 2, RoundingMode::Up).to_string(),
+            "0.34"
+        );
+        assert_eq!(
+            decimal("1.005").round(2, RoundingMode::HalfEven),
+            decimal("1.00")
+        );
+        assert_eq!(decimal("1.5").round(3, RoundingMode::Down).scale(), 1);
+    }
+}
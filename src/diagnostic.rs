@@ -0,0 +1,135 @@
+use crate::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// Renders an error along with the part of the input it refers to:
+///
+/// ```text
+/// error[E0102]: Can't divide by zero
+///  --> 1:9
+///   |
+/// 1 | 1 + 2 * (3 / (4 - 4))
+///   |         ^^^^^^^^^^^^^
+/// ```
+///
+/// Only the first line of a multi-line span is underlined.
+pub struct Diagnostic<'a> {
+    error: &'a Error,
+    source: &'a str,
+}
+
+impl<'a> Diagnostic<'a> {
+    /// Creates a diagnostic for an error, given the input it was returned for
+    pub fn new(error: &'a Error, source: &'a str) -> Diagnostic<'a> {
+        Diagnostic { error, source }
+    }
+}
+
+impl Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error[{}]: {}", self.error.code(), self.error)?;
+        let mut gutter = String::new();
+        if let Some(span) = self.error.span() {
+            let start = floor_char_boundary(self.source, span.start);
+            let end = floor_char_boundary(self.source, span.end.max(start));
+            let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = self.source[start..]
+                .find('\n')
+                .map_or(self.source.len(), |i| start + i);
+            let line = self.source[line_start..line_end].trim_end_matches('\r');
+            let line_number = (self.source[..start].matches('\n').count() + 1).to_string();
+            let column = self.source[line_start..start].chars().count();
+            let width = self.source[start..end.min(line_end)].chars().count().max(1);
+            gutter = " ".repeat(line_number.len());
+
+            write!(f, "\n{}--> {}:{}", gutter, line_number, column + 1)?;
+            write!(f, "\n{} |", gutter)?;
+            write!(f, "\n{} | {}", line_number, line)?;
+            write!(
+                f,
+                "\n{} | {}{}",
+                gutter,
+                " ".repeat(column),
+                "^".repeat(width)
+            )?;
+        }
+        if let Some(help) = self.error.help() {
+            write!(f, "\n{} = help: {}", gutter, help)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the offset of the character the byte offset falls within, or the
+/// length of the source if it is past the end, as spans may come from callers
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    (0..=offset.min(source.len()))
+        .rev()
+        .find(|&i| source.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ParseError;
+    use crate::span::Span;
+    use crate::Calculate;
+
+    fn render(input: &str) -> String {
+        Diagnostic::new(&input.calculate().unwrap_err(), input).to_string()
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        assert_eq!(
+            render("1 + 2 * (3 / (4 - 4))"),
+            "error[E0102]: Can't divide by zero
+ --> 1:9
+  |
+1 | 1 + 2 * (3 / (4 - 4))
+  |         ^^^^^^^^^^^^^"
+        );
+        assert_eq!(
+            render("1 +\n2 * qty"),
+            "error[E0101]: Unknown variable qty
+ --> 2:5
+  |
+2 | 2 * qty
+  |     ^^^
+  = help: bind the variable in the context passed to calculate_with"
+        );
+        assert_eq!(
+            render("(1 + 2"),
+            "error[E0003]: Unexpected end of input
+ --> 1:7
+  |
+1 | (1 + 2
+  |       ^
+  = help: the expression is incomplete"
+        );
+        // Spans inside a character are moved back to its start
+        let error = Error::Parse(ParseError::UnexpectedEnd, Span::new(1, 1));
+        assert_eq!(
+            Diagnostic::new(&error, "é").to_string(),
+            "error[E0003]: Unexpected end of input
+ --> 1:1
+  |
+1 | é
+  | ^
+  = help: the expression is incomplete"
+        );
+        let error = Error::Parse(ParseError::UnexpectedCharacter('$'), Span::new(3, 9));
+        assert_eq!(
+            Diagnostic::new(&error, "aé€$").to_string(),
+            "error[E0002]: Unexpected character $
+ --> 1:3
+  |
+1 | aé€$
+  |   ^^"
+        );
+        assert_eq!(
+            Diagnostic::new(&Error::custom("no"), "1").to_string(),
+            "error[E0100]: no"
+        );
+    }
+}
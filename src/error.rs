@@ -1,11 +1,212 @@
+use crate::diagnostic::Diagnostic;
+use crate::function::Arity;
+use crate::span::Span;
+use crate::Value;
 use std::fmt::{Display, Formatter};
 
-pub(crate) type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Error {
-    Parse(String),
-    Value(String),
+    /// The input could not be parsed, at the given location
+    Parse(ParseError, Span),
+    /// The expression could not be evaluated. The span is filled in with the
+    /// offending expression when not given, e.g. by native functions.
+    Value(ValueError, Option<Span>),
+    /// The input exceeded one of the calculator's [`EvalLimits`], while being
+    /// parsed or evaluated
+    ///
+    /// [`EvalLimits`]: crate::EvalLimits
+    Limit(LimitError, Option<Span>),
+}
+
+/// The kinds of error that can occur while lexing and parsing
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken(String),
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    ExpectedToken {
+        expected: String,
+        found: String,
+    },
+    ExpectedExpression(String),
+    InvalidNumber(String),
+    /// A number literal is incomplete or has a part in the wrong place, e.g.
+    /// an exponent without digits like `1e`
+    MalformedNumber {
+        number: String,
+        reason: &'static str,
+    },
+}
+
+/// The kinds of error that can occur while evaluating an expression
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueError {
+    /// An error raised by a native function
+    Custom(String),
+    UnknownVariable(String),
+    DivisionByZero,
+    IntegerOverflow,
+    /// A binary operation was applied to operands it doesn't support
+    InvalidOperands {
+        operation: &'static str,
+        lhs: Value,
+        rhs: Value,
+    },
+    /// A unary operation was applied to an operand it doesn't support
+    InvalidOperand {
+        operation: &'static str,
+        value: Value,
+    },
+    NegativeFactorial,
+    UnknownFunction(String),
+    ArityMismatch {
+        function: String,
+        expected: Arity,
+        found: usize,
+    },
+    InvalidArgument {
+        function: String,
+        expected: &'static str,
+        found: Value,
+    },
+}
+
+/// The kinds of limit that input can exceed, each with the limit
+#[derive(Clone, Debug, PartialEq)]
+pub enum LimitError {
+    InputTooLong {
+        max: usize,
+    },
+    NestingTooDeep {
+        max: usize,
+    },
+    TooManyNodes {
+        max: usize,
+    },
+    FactorialTooLarge {
+        max: u64,
+    },
+    /// The exponent of an exact power, the amount of a left shift or the
+    /// exponent of a decimal literal was too large
+    ExponentTooLarge {
+        max: u64,
+    },
+    OutOfFuel {
+        fuel: u64,
+    },
+}
+
+impl Error {
+    /// Creates a value error with a custom message, e.g. for native functions
+    pub fn custom<S: Into<String>>(message: S) -> Error {
+        Error::Value(ValueError::Custom(message.into()), None)
+    }
+
+    /// Returns the location of the error in the input, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Parse(_, span) => Some(*span),
+            Error::Value(_, span) | Error::Limit(_, span) => *span,
+        }
+    }
+
+    /// Returns a stable code identifying the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Parse(err, _) => err.code(),
+            Error::Value(err, _) => err.code(),
+            Error::Limit(err, _) => err.code(),
+        }
+    }
+
+    /// Returns a note on how the error may be fixed, if there is one
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            Error::Parse(err, _) => err.help(),
+            Error::Value(err, _) => err.help(),
+            Error::Limit(_, _) => Some("raise the limit with CalculatorBuilder::limits"),
+        }
+    }
+
+    /// Returns a renderer that shows the error against the input it came from
+    pub fn diagnostic<'a>(&'a self, source: &'a str) -> Diagnostic<'a> {
+        Diagnostic::new(self, source)
+    }
+
+    /// Attaches the span to the error if it doesn't have one yet
+    pub(crate) fn or_span(self, span: Span) -> Error {
+        match self {
+            Error::Value(err, None) => Error::Value(err, Some(span)),
+            Error::Limit(err, None) => Error::Limit(err, Some(span)),
+            err => err,
+        }
+    }
+}
+
+impl ParseError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::UnexpectedToken(_) => "E0001",
+            ParseError::UnexpectedCharacter(_) => "E0002",
+            ParseError::UnexpectedEnd => "E0003",
+            ParseError::ExpectedToken { .. } => "E0004",
+            ParseError::ExpectedExpression(_) => "E0005",
+            ParseError::InvalidNumber(_) => "E0006",
+            ParseError::MalformedNumber { .. } => "E0007",
+        }
+    }
+
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ParseError::UnexpectedToken(_) => Some("remove the token or add an operator before it"),
+            ParseError::UnexpectedEnd => Some("the expression is incomplete"),
+            _ => None,
+        }
+    }
+}
+
+impl ValueError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValueError::Custom(_) => "E0100",
+            ValueError::UnknownVariable(_) => "E0101",
+            ValueError::DivisionByZero => "E0102",
+            ValueError::IntegerOverflow => "E0103",
+            ValueError::InvalidOperands { .. } => "E0104",
+            ValueError::InvalidOperand { .. } => "E0105",
+            ValueError::NegativeFactorial => "E0106",
+            ValueError::UnknownFunction(_) => "E0107",
+            ValueError::ArityMismatch { .. } => "E0108",
+            ValueError::InvalidArgument { .. } => "E0109",
+        }
+    }
+
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ValueError::UnknownVariable(_) => {
+                Some("bind the variable in the context passed to calculate_with")
+            }
+            ValueError::UnknownFunction(_) => {
+                Some("register the function with Calculator::builder()")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl LimitError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            LimitError::InputTooLong { .. } => "E0200",
+            LimitError::NestingTooDeep { .. } => "E0201",
+            LimitError::TooManyNodes { .. } => "E0202",
+            LimitError::FactorialTooLarge { .. } => "E0203",
+            LimitError::ExponentTooLarge { .. } => "E0204",
+            LimitError::OutOfFuel { .. } => "E0205",
+        }
+    }
 }
 
 impl std::error::Error for Error {}
@@ -13,20 +214,97 @@ impl std::error::Error for Error {}
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::Parse(s) => f.write_str(s),
-            Error::Value(s) => f.write_str(s),
+            Error::Parse(err, _) => err.fmt(f),
+            Error::Value(err, _) => err.fmt(f),
+            Error::Limit(err, _) => err.fmt(f),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(token) => write!(f, "Unexpected token {}", token),
+            ParseError::UnexpectedCharacter(c) => write!(f, "Unexpected character {}", c),
+            ParseError::UnexpectedEnd => f.write_str("Unexpected end of input"),
+            ParseError::ExpectedToken { expected, found } => {
+                write!(f, "Expected token {}, found {}", expected, found)
+            }
+            ParseError::ExpectedExpression(found) => {
+                write!(f, "Expected expression atom, found {}", found)
+            }
+            ParseError::InvalidNumber(n) => write!(f, "Invalid number {}", n),
+            ParseError::MalformedNumber { number, reason } => {
+                write!(f, "Malformed number {}: {}", number, reason)
+            }
+        }
+    }
+}
+
+impl Display for ValueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueError::Custom(s) => f.write_str(s),
+            ValueError::UnknownVariable(name) => write!(f, "Unknown variable {}", name),
+            ValueError::DivisionByZero => f.write_str("Can't divide by zero"),
+            ValueError::IntegerOverflow => f.write_str("Integer overflow"),
+            ValueError::InvalidOperands {
+                operation,
+                lhs,
+                rhs,
+            } => write!(f, "Can't {} {} and {}", operation, lhs, rhs),
+            ValueError::InvalidOperand { operation, value } => {
+                write!(f, "Can't {} {}", operation, value)
+            }
+            ValueError::NegativeFactorial => f.write_str("Can't take factorial of negative number"),
+            ValueError::UnknownFunction(name) => write!(f, "Unknown function {}", name),
+            ValueError::ArityMismatch {
+                function,
+                expected,
+                found,
+            } => write!(f, "{} takes {}, found {}", function, expected, found),
+            ValueError::InvalidArgument {
+                function,
+                expected,
+                found,
+            } => write!(f, "{} expects {}, found {}", function, expected, found),
+        }
+    }
+}
+
+impl Display for LimitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitError::InputTooLong { max } => {
+                write!(f, "Input is longer than {} bytes", max)
+            }
+            LimitError::NestingTooDeep { max } => {
+                write!(f, "Expression is nested more than {} deep", max)
+            }
+            LimitError::TooManyNodes { max } => {
+                write!(f, "Expression has more than {} parts", max)
+            }
+            LimitError::FactorialTooLarge { max } => {
+                write!(f, "Can't take factorial of more than {}", max)
+            }
+            LimitError::ExponentTooLarge { max } => {
+                write!(f, "Exponent is larger than {}", max)
+            }
+            LimitError::OutOfFuel { fuel } => {
+                write!(f, "Evaluation took more than {} steps", fuel)
+            }
         }
     }
 }
 
-impl From<std::num::ParseFloatError> for Error {
-    fn from(err: std::num::ParseFloatError) -> Self {
-        Error::Parse(err.to_string())
+impl From<LimitError> for Error {
+    fn from(err: LimitError) -> Self {
+        Error::Limit(err, None)
     }
 }
 
-impl From<std::num::ParseIntError> for Error {
-    fn from(err: std::num::ParseIntError) -> Self {
-        Error::Parse(err.to_string())
+impl From<ValueError> for Error {
+    fn from(err: ValueError) -> Self {
+        Error::Value(err, None)
     }
 }
@@ -0,0 +1,310 @@
+use crate::bigint::BigInt;
+use crate::Value;
+
+/// The radix integers are formatted in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Radix {
+    Binary,
+    Octal,
+    #[default]
+    Decimal,
+    Hexadecimal,
+}
+
+impl Radix {
+    fn base(self) -> u32 {
+        match self {
+            Radix::Binary => 2,
+            Radix::Octal => 8,
+            Radix::Decimal => 10,
+            Radix::Hexadecimal => 16,
+        }
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            Radix::Binary => "0b",
+            Radix::Octal => "0o",
+            Radix::Decimal => "",
+            Radix::Hexadecimal => "0x",
+        }
+    }
+}
+
+/// The notation non-integer numbers are formatted in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Notation {
+    /// As [`Value`] displays them
+    #[default]
+    Auto,
+    /// Without an exponent, e.g. `1234.500`, with six fractional digits
+    /// unless a precision is given
+    Fixed,
+    /// With one digit before the point and an exponent, e.g. `1.2345e3`
+    Scientific,
+    /// With an exponent that is a multiple of three, e.g. `1.2345e3` or
+    /// `12.345e-3`
+    Engineering,
+}
+
+/// Formats values in the representation a user asked for, e.g. integers in
+/// hexadecimal or floats in scientific notation:
+///
+/// ```
+/// use calculator_rs::{Calculate, Radix, ValueFormatter};
+///
+/// let value = "0xDEAD_BEEF".calculate().unwrap();
+/// let formatter = ValueFormatter::new()
+///     .radix(Radix::Hexadecimal)
+///     .prefix(true)
+///     .group(4);
+/// assert_eq!(formatter.format(&value), "0xdead_beef");
+/// ```
+///
+/// Integers are formatted according to the radix, prefix and grouping, other
+/// numbers according to the notation and precision. Negative integers are
+/// formatted as a sign and a magnitude, e.g. `-0xff`.
+#[derive(Clone, Debug)]
+pub struct ValueFormatter {
+    radix: Radix,
+    prefix: bool,
+    uppercase: bool,
+    group: Option<usize>,
+    separator: char,
+    notation: Notation,
+    precision: Option<usize>,
+}
+
+impl Default for ValueFormatter {
+    fn default() -> Self {
+        ValueFormatter {
+            radix: Radix::default(),
+            prefix: false,
+            uppercase: false,
+            group: None,
+            separator: '_',
+            notation: Notation::default(),
+            precision: None,
+        }
+    }
+}
+
+impl ValueFormatter {
+    pub fn new() -> ValueFormatter {
+        ValueFormatter::default()
+    }
+
+    pub fn radix(mut self, radix: Radix) -> ValueFormatter {
+        self.radix = radix;
+        self
+    }
+
+    /// Prefixes integers with `0b`, `0o` or `0x` in the corresponding radix
+    pub fn prefix(mut self, prefix: bool) -> ValueFormatter {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Uses uppercase hexadecimal digits
+    pub fn uppercase(mut self, uppercase: bool) -> ValueFormatter {
+        self.uppercase = uppercase;
+        self
+    }
+
+    /// Separates integer digits into groups of the given size, counted from
+    /// the right
+    pub fn group(mut self, size: usize) -> ValueFormatter {
+        self.group = Some(size).filter(|size| *size > 0);
+        self
+    }
+
+    /// Sets the separator between groups of digits. Defaults to `_`, which
+    /// can be parsed back.
+    pub fn separator(mut self, separator: char) -> ValueFormatter {
+        self.separator = separator;
+        self
+    }
+
+    pub fn notation(mut self, notation: Notation) -> ValueFormatter {
+        self.notation = notation;
+        self
+    }
+
+    /// Sets the number of fractional digits, of the mantissa in scientific
+    /// and engineering notation
+    pub fn precision(mut self, precision: usize) -> ValueFormatter {
+        self.precision = Some(precision);
+        self
+    }
+
+    pub fn format(&self, value: &Value) -> String {
+        match value {
+            Value::Integer(i) => self.format_integer(&BigInt::from(*i)),
+            Value::BigInteger(i) => self.format_integer(i),
+            Value::Float(f) => self.format_float(*f),
+            Value::Decimal(d) if self.exponential() => self.format_float(d.to_f64()),
+            Value::Rational(r) if self.exponential() => self.format_float(r.to_f64()),
+            Value::Boolean(_) | Value::Decimal(_) | Value::Rational(_) | Value::Complex(_) => {
+                self.with_precision(value)
+            }
+        }
+    }
+
+    fn exponential(&self) -> bool {
+        matches!(self.notation, Notation::Scientific | Notation::Engineering)
+    }
+
+    fn with_precision(&self, value: &Value) -> String {
+        let precision = match self.notation {
+            Notation::Fixed => self.precision.or(Some(6)),
+            _ => self.precision,
+        };
+        match precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => value.to_string(),
+        }
+    }
+
+    fn format_integer(&self, i: &BigInt) -> String {
+        let mut digits = i.abs().to_str_radix(self.radix.base());
+        if self.uppercase {
+            digits.make_ascii_uppercase();
+        }
+        if let Some(size) = self.group {
+            digits = group(&digits, size, self.separator);
+        }
+        let sign = if i.is_negative() { "-" } else { "" };
+        let prefix = if self.prefix { self.radix.prefix() } else { "" };
+        format!("{}{}{}", sign, prefix, digits)
+    }
+
+    fn format_float(&self, f: f64) -> String {
+        if !f.is_finite() {
+            return f.to_string();
+        }
+        match (self.notation, self.precision) {
+            (Notation::Auto, None) => f.to_string(),
+            (Notation::Auto | Notation::Fixed, Some(precision)) => {
+                format!("{:.*}", precision, f)
+            }
+            (Notation::Fixed, None) => format!("{:.6}", f),
+            (Notation::Scientific, None) => format!("{:e}", f),
+            (Notation::Scientific, Some(precision)) => format!("{:.*e}", precision, f),
+            (Notation::Engineering, precision) => engineering(f, precision),
+        }
+    }
+}
+
+/// Formats a float with an exponent that is a multiple of three, by shifting
+/// the point of its scientific representation
+fn engineering(f: f64, precision: Option<usize>) -> String {
+    let exponent = |s: &str| {
+        s.split_once('e')
+            .and_then(|(_, exponent)| exponent.parse::<i32>().ok())
+            .unwrap_or(0)
+    };
+    let shift = |s: &str| exponent(s).rem_euclid(3) as usize;
+    let mut s = format!("{:e}", f);
+    if let Some(precision) = precision {
+        s = format!("{:.*e}", precision + shift(&s), f);
+    }
+    // Rounding may have carried into the next power of ten, leaving zeros to
+    // add or drop
+    let (shift, exp) = (shift(&s), exponent(&s));
+    let mantissa = s.split('e').next().unwrap_or_default();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let mut digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let len = match precision {
+        Some(precision) => shift + 1 + precision,
+        None => digits.len().max(shift + 1),
+    };
+    digits.truncate(len);
+    digits.push_str(&"0".repeat(len - digits.len()));
+    let (int, frac) = digits.split_at(shift + 1);
+    let point = if frac.is_empty() { "" } else { "." };
+    format!("{}{}{}{}e{}", sign, int, point, frac, exp - shift as i32)
+}
+
+/// Separates digits into groups of the given size, counted from the right
+fn group(digits: &str, size: usize, separator: char) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && i % size == digits.len() % size {
+            grouped.push(separator);
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decimal::Decimal;
+    use crate::Calculate;
+
+    fn format(formatter: &ValueFormatter, input: &str) -> String {
+        formatter.format(&input.calculate().unwrap())
+    }
+
+    #[test]
+    fn test_format_integer() {
+        let hex = ValueFormatter::new().radix(Radix::Hexadecimal);
+        assert_eq!(format(&hex, "255"), "ff");
+        assert_eq!(format(&hex.clone().prefix(true), "-255"), "-0xff");
+        assert_eq!(
+            format(
+                &hex.clone().uppercase(true).prefix(true).group(4),
+                "2^40 - 1"
+            ),
+            "0xFF_FFFF_FFFF"
+        );
+        let binary = ValueFormatter::new().radix(Radix::Binary).group(4);
+        assert_eq!(format(&binary, "0b101_1010"), "101_1010");
+        assert_eq!(format(&binary.prefix(true), "0"), "0b0");
+        let octal = ValueFormatter::new().radix(Radix::Octal).prefix(true);
+        assert_eq!(format(&octal, "493"), "0o755");
+        let decimal = ValueFormatter::new().group(3).separator(',');
+        assert_eq!(format(&decimal, "1234567"), "1,234,567");
+        assert_eq!(format(&decimal, "-123"), "-123");
+        // Non-integers aren't affected by the radix
+        assert_eq!(format(&hex, "1.5"), "1.5");
+    }
+
+    #[test]
+    fn test_format_float() {
+        let fixed = ValueFormatter::new().notation(Notation::Fixed);
+        assert_eq!(format(&fixed, "1234.5"), "1234.500000");
+        assert_eq!(format(&fixed, "2/3"), "0.666667");
+        assert_eq!(format(&fixed, "1/8 + 1i"), "0.125000+1.000000i");
+        let decimal = Value::Decimal(Decimal::new(BigInt::from(12345i64), 1));
+        assert_eq!(fixed.format(&decimal), "1234.500000");
+        assert_eq!(format(&fixed.clone().precision(2), "2/3"), "0.67");
+        let scientific = ValueFormatter::new().notation(Notation::Scientific);
+        assert_eq!(format(&scientific, "6.022e23"), "6.022e23");
+        assert_eq!(
+            format(&scientific.clone().precision(2), "0.000123456"),
+            "1.23e-4"
+        );
+        assert_eq!(format(&scientific, "1/4"), "2.5e-1");
+        let engineering = ValueFormatter::new().notation(Notation::Engineering);
+        assert_eq!(format(&engineering, "1234.5"), "1.2345e3");
+        assert_eq!(format(&engineering, "12345.0"), "12.345e3");
+        assert_eq!(format(&engineering, "0.000123"), "123e-6");
+        assert_eq!(format(&engineering, "-0.5"), "-500e-3");
+        assert_eq!(format(&engineering, "1.0"), "1e0");
+        let engineering = engineering.precision(2);
+        assert_eq!(format(&engineering, "12346.0"), "12.35e3");
+        assert_eq!(format(&engineering, "99.996"), "100.00e0");
+        assert_eq!(format(&engineering, "999999.0"), "1.00e6");
+        assert_eq!(format(&engineering, "0.0"), "0.00e0");
+        assert_eq!(format(&ValueFormatter::new().precision(3), "1.0"), "1.000");
+        assert_eq!(
+            format(&ValueFormatter::new().precision(1), "3+4i"),
+            "3.0+4.0i"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use crate::error::{Result, ValueError};
+use crate::{BigInt, Calculator, Complex, Decimal, Rational, RoundingMode, Value};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+/// The number of arguments a function accepts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    /// Exactly this many arguments
+    Exact(usize),
+    /// At least this many arguments
+    AtLeast(usize),
+    /// Between min and max arguments, inclusive
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, len: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => len == n,
+            Arity::AtLeast(min) => len >= min,
+            Arity::Range(min, max) => (min..=max).contains(&len),
+        }
+    }
+
+    /// Checks the number of arguments passed to the named function
+    pub(crate) fn check(&self, name: &str, len: usize) -> Result<()> {
+        if self.accepts(len) {
+            Ok(())
+        } else {
+            Err(ValueError::ArityMismatch {
+                function: name.into(),
+                expected: *self,
+                found: len,
+            }
+            .into())
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match *self {
+            Arity::Exact(n) => write!(f, "{} argument{}", n, plural(n)),
+            Arity::AtLeast(min) => write!(f, "at least {} argument{}", min, plural(min)),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Arity::Exact(n)
+    }
+}
+
+type NativeFn = dyn Fn(&[Value]) -> Result<Value> + Send + Sync;
+
+/// A native function registered on a calculator
+#[derive(Clone)]
+pub(crate) struct Function {
+    pub(crate) arity: Arity,
+    pub(crate) func: Arc<NativeFn>,
+}
+
+impl Function {
+    pub(crate) fn call(&self, name: &str, args: &[Value]) -> Result<Value> {
+        self.arity.check(name, args.len())?;
+        (self.func)(args)
+    }
+}
+
+/// The number of digits `round` accepts on either side of the point
+const MAX_ROUND_DIGITS: i64 = 1000;
+
+/// Calls a built-in function with already evaluated arguments, rounding
+/// decimals with the given mode
+pub(crate) fn call(name: &str, args: Vec<Value>, rounding: RoundingMode) -> Result<Value> {
+    match name {
+        "abs" => {
+            let [x] = exact(name, args)?;
+            match x {
+                Value::Integer(i) => Ok(i
+                    .checked_abs()
+                    .map_or_else(|| BigInt::from(i).abs().into(), Value::Integer)),
+                Value::BigInteger(i) => Ok(Value::BigInteger(i.abs())),
+                Value::Decimal(d) => Ok(Value::Decimal(d.abs())),
+                Value::Rational(r) => Ok(Value::Rational(r.abs())),
+                Value::Complex(c) => Ok(Value::Float(c.norm())),
+                x => Ok(Value::Float(float(name, x)?.abs())),
+            }
+        }
+        "ceil" => round_with(name, args, f64::ceil, Rational::ceil),
+        "cos" => unary(name, args, f64::cos),
+        "exp" => complex_unary(name, args, f64::exp, Complex::exp),
+        "floor" => round_with(name, args, f64::floor, Rational::floor),
+        "ln" => complex_unary(name, args, f64::ln, Complex::ln),
+        "log" => match optional(name, args)? {
+            (x, None) => Ok(Value::Float(float(name, x)?.log10())),
+            (x, Some(base)) => Ok(Value::Float(float(name, x)?.log(float(name, base)?))),
+        },
+        "max" => extremum(name, args, Ordering::Greater),
+        "min" => extremum(name, args, Ordering::Less),
+        "round" => match optional(name, args)? {
+            (x, None) => round_with(name, vec![x], f64::round, Rational::round),
+            (x, Some(digits)) => {
+                let digits = match digits {
+                    Value::Integer(digits)
+                        if (-MAX_ROUND_DIGITS..=MAX_ROUND_DIGITS).contains(&digits) =>
+                    {
+                        digits as i32
+                    }
+                    other => {
+                        return Err(ValueError::InvalidArgument {
+                            function: name.into(),
+                            expected: "an integer number of digits from -1000 to 1000",
+                            found: other,
+                        }
+                        .into());
+                    }
+                };
+                round_digits(name, x, digits, rounding)
+            }
+        },
+        "sin" => unary(name, args, f64::sin),
+        "sqrt" => match exact(name, args)? {
+            [Value::Complex(c)] => Ok(c.sqrt().into()),
+            [x] => match float(name, x)? {
+                x if x < 0.0 => Ok(Complex::from(x).sqrt().into()),
+                x => Ok(Value::Float(x.sqrt())),
+            },
+        },
+        "tan" => unary(name, args, f64::tan),
+        _ => Err(ValueError::UnknownFunction(name.into()).into()),
+    }
+}
+
+/// Destructures the arguments, checking that exactly N were given
+fn exact<const N: usize>(name: &str, args: Vec<Value>) -> Result<[Value; N]> {
+    Arity::Exact(N).check(name, args.len())?;
+    Ok(args.try_into().unwrap_or_else(|_| unreachable!()))
+}
+
+/// Destructures one required and one optional argument
+fn optional(name: &str, args: Vec<Value>) -> Result<(Value, Option<Value>)> {
+    Arity::Range(1, 2).check(name, args.len())?;
+    let mut args = args.into_iter();
+    Ok((args.next().unwrap_or_else(|| unreachable!()), args.next()))
+}
+
+/// Checks that an argument is a real number, without converting it
+fn check_real(name: &str, value: &Value) -> Result<()> {
+    match value {
+        Value::Integer(_)
+        | Value::BigInteger(_)
+        | Value::Decimal(_)
+        | Value::Rational(_)
+        | Value::Float(_) => Ok(()),
+        other => float(name, other.clone()).map(|_| ()),
+    }
+}
+
+/// Converts a numeric argument to a float
+fn float(name: &str, value: Value) -> Result<f64> {
+    match value {
+        Value::Integer(i) => Ok(i as f64),
+        Value::BigInteger(i) => Ok(i.to_f64()),
+        Value::Decimal(d) => Ok(d.to_f64()),
+        Value::Rational(r) => Ok(r.to_f64()),
+        Value::Float(f) => Ok(f),
+        Value::Complex(_) => Err(ValueError::InvalidArgument {
+            function: name.into(),
+            expected: "a real number",
+            found: value,
+        }
+        .into()),
+        other => Err(ValueError::InvalidArgument {
+            function: name.into(),
+            expected: "a number",
+            found: other,
+        }
+        .into()),
+    }
+}
+
+fn unary(name: &str, args: Vec<Value>, f: fn(f64) -> f64) -> Result<Value> {
+    let [x] = exact(name, args)?;
+    Ok(Value::Float(f(float(name, x)?)))
+}
+
+/// Applies the real function, or the complex one to complex arguments
+fn complex_unary(
+    name: &str,
+    args: Vec<Value>,
+    f: fn(f64) -> f64,
+    c: fn(&Complex) -> Complex,
+) -> Result<Value> {
+    match exact(name, args)? {
+        [Value::Complex(x)] => Ok(c(&x).into()),
+        [x] => Ok(Value::Float(f(float(name, x)?))),
+    }
+}
+
+/// Rounds floats and rationals with the given functions, integers are already
+/// whole. Rationals are rounded exactly, to an integer.
+fn round_with(
+    name: &str,
+    args: Vec<Value>,
+    f: fn(f64) -> f64,
+    r: fn(&Rational) -> BigInt,
+) -> Result<Value> {
+    let [x] = exact(name, args)?;
+    match x {
+        x @ (Value::Integer(_) | Value::BigInteger(_)) => Ok(x),
+        Value::Decimal(x) => Ok(r(&x.to_rational()).into()),
+        Value::Rational(x) => Ok(r(&x).into()),
+        x => Ok(Value::Float(f(float(name, x)?))),
+    }
+}
+
+/// Rounds to the number of fractional digits, or to a multiple of a power of
+/// ten if it is negative. Integers stay integers, and decimals are rounded with
+/// the mode while other values are rounded half away from zero.
+fn round_digits(name: &str, x: Value, digits: i32, rounding: RoundingMode) -> Result<Value> {
+    let unit = || BigInt::from(10i64).pow(digits.unsigned_abs());
+    let round_integer = |i: BigInt| {
+        let unit = unit();
+        let rounded = Rational::new(i, unit.clone()).unwrap_or_else(|| unreachable!());
+        Ok((rounded.round() * unit).into())
+    };
+    match x {
+        x @ (Value::Integer(_) | Value::BigInteger(_)) if digits >= 0 => Ok(x),
+        Value::Integer(i) => round_integer(BigInt::from(i)),
+        Value::BigInteger(i) => round_integer(i),
+        Value::Decimal(x) => match u32::try_from(digits) {
+            Ok(digits) => Ok(Value::Decimal(x.round(digits, rounding))),
+            Err(_) => {
+                // Round the decimal divided by the unit to a whole number
+                let scale = x.scale().saturating_add(digits.unsigned_abs());
+                let whole = Decimal::new(x.mantissa().clone(), scale).round(0, rounding);
+                Ok(Value::Decimal(Decimal::new(whole.mantissa() * &unit(), 0)))
+            }
+        },
+        x => {
+            let x = float(name, x)?;
+            let scale = 10f64.powi(digits.abs());
+            Ok(Value::Float(if !x.is_finite() {
+                x
+            } else if digits >= 0 {
+                // Floats too large to scale have no fractional digits
+                match x * scale {
+                    scaled if scaled.is_finite() => scaled.round() / scale,
+                    _ => x,
+                }
+            } else if scale.is_finite() {
+                (x / scale).round() * scale
+            } else {
+                0f64.copysign(x)
+            }))
+        }
+    }
+}
+
+/// Returns the argument that compares as `ordering` against all others
+fn extremum(name: &str, args: Vec<Value>, ordering: Ordering) -> Result<Value> {
+    Arity::AtLeast(1).check(name, args.len())?;
+    let mut args = args.into_iter();
+    let mut result = args.next().unwrap_or_else(|| unreachable!());
+    check_real(name, &result)?;
+    for arg in args {
+        check_real(name, &arg)?;
+        if Calculator::compare(&arg, &result)? == Some(ordering) {
+            result = arg;
+        }
+    }
+    Ok(result)
+}
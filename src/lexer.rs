@@ -1,80 +1,152 @@
-use crate::error::{Error, Result};
+use crate::error::{Error, ParseError, Result};
+use crate::span::Span;
 use std::fmt::{Display, Formatter};
 use std::iter::Peekable;
-use std::str::Chars;
+use std::str::CharIndices;
 
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Token {
     Number(String),
+    Ident(String),
+    /// A comment from `#` to the end of the line, without the `#`
+    Comment(String),
+    Ampersand,
+    And,
     Asterisk,
     Caret,
     CloseParen,
+    Colon,
+    Comma,
+    DoubleAmpersand,
+    DoubleGreaterThan,
+    DoubleLessThan,
+    DoublePipe,
     Equal,
     Exclamation,
+    ExclamationEqual,
+    False,
     GreaterThan,
     GreaterThanOrEqual,
     LessOrGreaterThan,
     LessThan,
     LessThanOrEqual,
     Minus,
+    Not,
     OpenParen,
+    Or,
     Percent,
+    Pipe,
     Plus,
+    Question,
     Slash,
+    Tilde,
+    True,
+    Xor,
 }
 
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Token::Comment(s) = self {
+            return write!(f, "#{}", s);
+        }
         f.write_str(match self {
             Token::Number(s) => s,
+            Token::Ident(s) => s,
+            Token::Comment(_) => unreachable!("comments are written above"),
+            Token::Ampersand => "&",
+            Token::And => "and",
             Token::Asterisk => "*",
             Token::Caret => "^",
+            Token::DoubleAmpersand => "&&",
+            Token::DoubleGreaterThan => ">>",
+            Token::DoubleLessThan => "<<",
+            Token::DoublePipe => "||",
             Token::Equal => "=",
+            Token::ExclamationEqual => "!=",
             Token::GreaterThan => ">",
             Token::GreaterThanOrEqual => ">=",
             Token::LessOrGreaterThan => "<>",
             Token::LessThan => "<",
             Token::LessThanOrEqual => "<=",
             Token::Minus => "-",
+            Token::Not => "not",
+            Token::Or => "or",
             Token::Percent => "%",
+            Token::Pipe => "|",
             Token::Plus => "+",
             Token::Slash => "/",
             Token::OpenParen => "(",
             Token::CloseParen => ")",
+            Token::Comma => ",",
+            Token::Colon => ":",
+            Token::Question => "?",
             Token::Exclamation => "!",
+            Token::Tilde => "~",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Xor => "xor",
         })
     }
 }
 
 pub(crate) struct Lexer<'a> {
-    iter: Peekable<Chars<'a>>,
+    input: &'a str,
+    iter: Peekable<CharIndices<'a>>,
+    /// Whether comments are returned as tokens rather than skipped like
+    /// whitespace
+    comments: bool,
 }
 
 impl Iterator for Lexer<'_> {
-    type Item = Result<Token>;
+    type Item = Result<(Token, Span)>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.consume_space();
+        let start = self.offset();
         match self.scan() {
-            Some(token) => Some(Ok(token)),
-            None => self
-                .iter
-                .next()
-                .map(|c| Err(Error::Parse(format!("Unexpected character {}", c)))),
+            Ok(Some(token)) => Some(Ok((token, Span::new(start, self.offset())))),
+            Ok(None) => self.iter.next().map(|(_, c)| {
+                Err(Error::Parse(
+                    ParseError::UnexpectedCharacter(c),
+                    Span::new(start, self.offset()),
+                ))
+            }),
+            Err(err) => Some(Err(err)),
         }
     }
 }
 
-impl Lexer<'_> {
-    pub(crate) fn new(input: &str) -> Lexer {
+impl<'a> Lexer<'a> {
+    pub(crate) fn new(input: &'a str) -> Lexer<'a> {
         Lexer {
-            iter: input.chars().peekable(),
+            input,
+            iter: input.char_indices().peekable(),
+            comments: false,
         }
     }
 
+    /// Returns comments as tokens, for tools that rewrite the source
+    pub(crate) fn with_comments(mut self) -> Lexer<'a> {
+        self.comments = true;
+        self
+    }
+
+    /// Returns the byte offset of the next character
+    pub(crate) fn offset(&mut self) -> usize {
+        self.iter
+            .peek()
+            .map_or(self.input.len(), |(offset, _)| *offset)
+    }
+
+    /// Returns the input following the next character
+    fn rest(&mut self) -> &'a str {
+        let offset = self.offset();
+        &self.input[offset..]
+    }
+
     fn next_if<F: Fn(char) -> bool>(&mut self, predicate: F) -> Option<char> {
-        self.iter.peek().filter(|&c| predicate(*c))?;
-        self.iter.next()
+        self.iter.peek().filter(|(_, c)| predicate(*c))?;
+        self.iter.next().map(|(_, c)| c)
     }
 
     fn next_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> Option<String> {
@@ -87,32 +159,148 @@ impl Lexer<'_> {
 
     fn consume_space(&mut self) {
         self.next_while(|c| c.is_whitespace());
+        while !self.comments && self.next_if(|c| c == '#').is_some() {
+            self.next_while(|c| c != '\n');
+            self.next_while(|c| c.is_whitespace());
+        }
     }
 
     fn next_if_token<F: Fn(char) -> Option<Token>>(&mut self, tokenizer: F) -> Option<Token> {
-        let token = self.iter.peek().and_then(|c| tokenizer(*c))?;
+        let token = self.iter.peek().and_then(|(_, c)| tokenizer(*c))?;
         self.iter.next();
         Some(token)
     }
 
-    fn scan(&mut self) -> Option<Token> {
+    fn scan(&mut self) -> Result<Option<Token>> {
         self.consume_space();
-        match self.iter.peek() {
-            Some(c) if c.is_ascii_digit() => self.scan_number(),
-            Some(_) => self.scan_symbol(),
-            None => None,
+        let mut rest = self.rest().chars();
+        match rest.next() {
+            Some(c) if c.is_ascii_digit() => self.scan_number().map(Some),
+            Some('.') if rest.next().is_some_and(|c| c.is_ascii_digit()) => {
+                self.scan_number().map(Some)
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => Ok(self.scan_ident()),
+            Some('#') => Ok(self.scan_comment()),
+            Some(_) => Ok(self.scan_symbol()),
+            None => Ok(None),
         }
     }
 
-    fn scan_number(&mut self) -> Option<Token> {
-        let mut num = self.next_while(|c| c.is_ascii_digit())?;
+    /// Scans a number like `12`, `1.5`, `.5`, `6.022e23` or `1_000`, with an
+    /// optional `d` or `i` suffix, or an integer with a radix prefix
+    fn scan_number(&mut self) -> Result<Token> {
+        let mut rest = self.rest().chars();
+        if let (Some('0'), Some(prefix)) = (rest.next(), rest.next()) {
+            match prefix {
+                'x' | 'X' => return self.scan_radix(16),
+                'o' | 'O' => return self.scan_radix(8),
+                'b' | 'B' => return self.scan_radix(2),
+                _ => {}
+            }
+        }
+        let start = self.offset();
+        let digits = |c: char| c.is_ascii_digit() || c == '_';
+        let mut num = self.next_while(digits).unwrap_or_default();
         if let Some(sep) = self.next_if(|c| c == '.') {
-            num.push(sep)
+            num.push(sep);
+            num.push_str(&self.next_while(digits).unwrap_or_default());
+            if self.rest().starts_with('.') {
+                let dot = self.offset();
+                self.next_while(|c| digits(c) || c == '.');
+                let span = Span::new(dot, self.offset());
+                return Err(self.malformed(
+                    start,
+                    span,
+                    "a number can only have one decimal point",
+                ));
+            }
+        }
+        // An `e` is an exponent unless it starts an identifier, as in `2exp`
+        let rest = self.rest();
+        if rest.starts_with(['e', 'E']) && !rest[1..].starts_with(|c: char| c.is_alphabetic()) {
+            let e = self.offset();
+            num.extend(self.iter.next().map(|(_, c)| c));
+            num.extend(self.next_if(|c| c == '+' || c == '-'));
+            let exponent = self.next_while(digits).unwrap_or_default();
+            if !exponent.contains(|c: char| c.is_ascii_digit()) {
+                let span = Span::new(e, self.offset());
+                return Err(self.malformed(start, span, "the exponent has no digits"));
+            }
+            num.push_str(&exponent);
+        }
+        // A `d` or `i` suffix marks a decimal or imaginary literal, unless it
+        // starts an identifier
+        let rest = self.rest();
+        if let Some(suffix) = rest.chars().next().filter(|c| matches!(c, 'd' | 'i')) {
+            if !rest[1..].starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+                self.iter.next();
+                num.push(suffix);
+            }
+        }
+        Ok(Token::Number(num))
+    }
+
+    /// Scans an integer with a `0x`, `0o` or `0b` prefix like `0xFF_FF`
+    fn scan_radix(&mut self, radix: u32) -> Result<Token> {
+        let start = self.offset();
+        let mut num: String = self.iter.by_ref().take(2).map(|(_, c)| c).collect();
+        let mut invalid = None;
+        while let Some(&(offset, c)) = self.iter.peek() {
+            if !c.is_alphanumeric() && c != '_' {
+                break;
+            }
+            if !c.is_digit(radix) && c != '_' && invalid.is_none() {
+                invalid = Some(Span::new(offset, offset + c.len_utf8()));
+            }
+            num.push(c);
+            self.iter.next();
+        }
+        if let Some(span) = invalid {
+            let reason = match radix {
+                2 => "binary literals only have the digits 0 and 1",
+                8 => "octal literals only have the digits 0 to 7",
+                _ => "hexadecimal literals only have the digits 0 to 9 and a to f",
+            };
+            return Err(self.malformed(start, span, reason));
         }
-        if let Some(dec) = self.next_while(|c| c.is_ascii_digit()) {
-            num.push_str(&dec);
+        if !num[2..].contains(|c: char| c.is_digit(radix)) {
+            let span = Span::new(start, self.offset());
+            return Err(self.malformed(start, span, "the literal has no digits"));
         }
-        Some(Token::Number(num))
+        Ok(Token::Number(num))
+    }
+
+    /// Returns an error for the number starting at `start` and ending at the
+    /// current offset, locating it at the offending part
+    fn malformed(&mut self, start: usize, span: Span, reason: &'static str) -> Error {
+        let end = self.offset();
+        Error::Parse(
+            ParseError::MalformedNumber {
+                number: self.input[start..end].into(),
+                reason,
+            },
+            span,
+        )
+    }
+
+    fn scan_ident(&mut self) -> Option<Token> {
+        let ident = self.next_while(|c| c.is_alphanumeric() || c == '_')?;
+        Some(match ident.as_str() {
+            "and" => Token::And,
+            "false" => Token::False,
+            "not" => Token::Not,
+            "or" => Token::Or,
+            "true" => Token::True,
+            "xor" => Token::Xor,
+            _ => Token::Ident(ident),
+        })
+    }
+
+    /// Scans a comment, leaving out the line break and any trailing space
+    fn scan_comment(&mut self) -> Option<Token> {
+        self.next_if(|c| c == '#')?;
+        let comment = self.next_while(|c| c != '\n')?;
+        Some(Token::Comment(comment.trim_end().into()))
     }
 
     fn scan_symbol(&mut self) -> Option<Token> {
@@ -128,7 +316,13 @@ impl Lexer<'_> {
             '%' => Some(Token::Percent),
             '(' => Some(Token::OpenParen),
             ')' => Some(Token::CloseParen),
+            ',' => Some(Token::Comma),
+            '?' => Some(Token::Question),
+            ':' => Some(Token::Colon),
             '!' => Some(Token::Exclamation),
+            '&' => Some(Token::Ampersand),
+            '|' => Some(Token::Pipe),
+            '~' => Some(Token::Tilde),
             _ => None,
         })
         .map(|token| match token {
@@ -137,6 +331,8 @@ impl Lexer<'_> {
                     Token::LessOrGreaterThan
                 } else if self.next_if(|c| c == '=').is_some() {
                     Token::LessThanOrEqual
+                } else if self.next_if(|c| c == '<').is_some() {
+                    Token::DoubleLessThan
                 } else {
                     token
                 }
@@ -144,10 +340,17 @@ impl Lexer<'_> {
             Token::GreaterThan => {
                 if self.next_if(|c| c == '=').is_some() {
                     Token::GreaterThanOrEqual
+                } else if self.next_if(|c| c == '>').is_some() {
+                    Token::DoubleGreaterThan
                 } else {
                     token
                 }
             }
+            // `3!=6` is a comparison rather than a factorial, as in most
+            // languages
+            Token::Exclamation if self.next_if(|c| c == '=').is_some() => Token::ExclamationEqual,
+            Token::Ampersand if self.next_if(|c| c == '&').is_some() => Token::DoubleAmpersand,
+            Token::Pipe if self.next_if(|c| c == '|').is_some() => Token::DoublePipe,
             _ => token,
         })
     }
@@ -166,7 +369,7 @@ mod tests {
             }
         }
         {
-            let mut lexer = Lexer::new("1 + +m+");
+            let mut lexer = Lexer::new("1 + +$+");
             assert!(lexer.next().unwrap().is_ok());
             assert!(lexer.next().unwrap().is_ok());
             assert!(lexer.next().unwrap().is_ok());
@@ -175,7 +378,7 @@ mod tests {
         }
         {
             let lexer = Lexer::new("1.2++=+");
-            let left: Vec<_> = lexer.collect();
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
             assert_eq!(
                 left,
                 vec![
@@ -187,5 +390,233 @@ mod tests {
                 ]
             );
         }
+        {
+            let lexer = Lexer::new("12.50d*2i+3days");
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Number("12.50d".into())),
+                    Ok(Token::Asterisk),
+                    Ok(Token::Number("2i".into())),
+                    Ok(Token::Plus),
+                    Ok(Token::Number("3".into())),
+                    Ok(Token::Ident("days".into())),
+                ]
+            );
+        }
+        {
+            let lexer = Lexer::new("a<<2>>1&b|~c xor xor2");
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Ident("a".into())),
+                    Ok(Token::DoubleLessThan),
+                    Ok(Token::Number("2".into())),
+                    Ok(Token::DoubleGreaterThan),
+                    Ok(Token::Number("1".into())),
+                    Ok(Token::Ampersand),
+                    Ok(Token::Ident("b".into())),
+                    Ok(Token::Pipe),
+                    Ok(Token::Tilde),
+                    Ok(Token::Ident("c".into())),
+                    Ok(Token::Xor),
+                    Ok(Token::Ident("xor2".into())),
+                ]
+            );
+        }
+        {
+            let input = "# total\n1 + # one\n\n  2 #two \r\n";
+            let lexer = Lexer::new(input);
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Number("1".into())),
+                    Ok(Token::Plus),
+                    Ok(Token::Number("2".into())),
+                ]
+            );
+            let lexer = Lexer::new(input).with_comments();
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Comment(" total".into())),
+                    Ok(Token::Number("1".into())),
+                    Ok(Token::Plus),
+                    Ok(Token::Comment(" one".into())),
+                    Ok(Token::Number("2".into())),
+                    Ok(Token::Comment("two".into())),
+                ]
+            );
+        }
+        {
+            let lexer = Lexer::new("1!=2 <> 3! = 4");
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Number("1".into())),
+                    Ok(Token::ExclamationEqual),
+                    Ok(Token::Number("2".into())),
+                    Ok(Token::LessOrGreaterThan),
+                    Ok(Token::Number("3".into())),
+                    Ok(Token::Exclamation),
+                    Ok(Token::Equal),
+                    Ok(Token::Number("4".into())),
+                ]
+            );
+        }
+        {
+            let lexer = Lexer::new("not a&&b||true and false or nothing");
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Not),
+                    Ok(Token::Ident("a".into())),
+                    Ok(Token::DoubleAmpersand),
+                    Ok(Token::Ident("b".into())),
+                    Ok(Token::DoublePipe),
+                    Ok(Token::True),
+                    Ok(Token::And),
+                    Ok(Token::False),
+                    Ok(Token::Or),
+                    Ok(Token::Ident("nothing".into())),
+                ]
+            );
+        }
+        {
+            let lexer = Lexer::new("unit_price*qty2");
+            let left: Vec<_> = lexer.map(|r| r.map(|(token, _)| token)).collect();
+            assert_eq!(
+                left,
+                vec![
+                    Ok(Token::Ident("unit_price".into())),
+                    Ok(Token::Asterisk),
+                    Ok(Token::Ident("qty2".into())),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_lexer_number() {
+        let tokens = |input| {
+            Lexer::new(input)
+                .map(|r| r.map(|(token, _)| token))
+                .collect::<Result<Vec<_>>>()
+        };
+        let number = |n: &str| Token::Number(n.into());
+        assert_eq!(tokens("6.022e23"), Ok(vec![number("6.022e23")]));
+        assert_eq!(tokens("1E-9"), Ok(vec![number("1E-9")]));
+        assert_eq!(tokens("2e+3i"), Ok(vec![number("2e+3i")]));
+        assert_eq!(
+            tokens(".5+1."),
+            Ok(vec![number(".5"), Token::Plus, number("1.")])
+        );
+        assert_eq!(
+            tokens("0xFF_FF+0b1010*0o755-1_000.5"),
+            Ok(vec![
+                number("0xFF_FF"),
+                Token::Plus,
+                number("0b1010"),
+                Token::Asterisk,
+                number("0o755"),
+                Token::Minus,
+                number("1_000.5"),
+            ])
+        );
+        assert_eq!(
+            tokens("1 + 0b102"),
+            Err(Error::Parse(
+                ParseError::MalformedNumber {
+                    number: "0b102".into(),
+                    reason: "binary literals only have the digits 0 and 1",
+                },
+                Span::new(8, 9)
+            ))
+        );
+        assert_eq!(
+            tokens("0o78"),
+            Err(Error::Parse(
+                ParseError::MalformedNumber {
+                    number: "0o78".into(),
+                    reason: "octal literals only have the digits 0 to 7",
+                },
+                Span::new(3, 4)
+            ))
+        );
+        assert_eq!(
+            tokens("0x_"),
+            Err(Error::Parse(
+                ParseError::MalformedNumber {
+                    number: "0x_".into(),
+                    reason: "the literal has no digits",
+                },
+                Span::new(0, 3)
+            ))
+        );
+        assert_eq!(
+            tokens("2exp"),
+            Ok(vec![number("2"), Token::Ident("exp".into())])
+        );
+        assert_eq!(
+            tokens("1 + 1e"),
+            Err(Error::Parse(
+                ParseError::MalformedNumber {
+                    number: "1e".into(),
+                    reason: "the exponent has no digits",
+                },
+                Span::new(5, 6)
+            ))
+        );
+        assert_eq!(
+            tokens("1e-)"),
+            Err(Error::Parse(
+                ParseError::MalformedNumber {
+                    number: "1e-".into(),
+                    reason: "the exponent has no digits",
+                },
+                Span::new(1, 3)
+            ))
+        );
+        assert_eq!(
+            tokens("1.2.3"),
+            Err(Error::Parse(
+                ParseError::MalformedNumber {
+                    number: "1.2.3".into(),
+                    reason: "a number can only have one decimal point",
+                },
+                Span::new(3, 5)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_lexer_span() {
+        let lexer = Lexer::new(" 12 >=  é+ 3.5");
+        let left: Vec<_> = lexer.collect();
+        assert_eq!(
+            left,
+            vec![
+                Ok((Token::Number("12".into()), Span::new(1, 3))),
+                Ok((Token::GreaterThanOrEqual, Span::new(4, 6))),
+                Ok((Token::Ident("é".into()), Span::new(8, 10))),
+                Ok((Token::Plus, Span::new(10, 11))),
+                Ok((Token::Number("3.5".into()), Span::new(12, 15))),
+            ]
+        );
+        let mut lexer = Lexer::new("1 $");
+        lexer.next();
+        assert_eq!(
+            lexer.next(),
+            Some(Err(Error::Parse(
+                ParseError::UnexpectedCharacter('$'),
+                Span::new(2, 3)
+            )))
+        );
     }
 }
@@ -1,24 +1,190 @@
-use crate::ast::{Expression, Literal, Operation};
-use crate::error::{Error, Result};
+use crate::ast::{Expression, ExpressionKind, Literal, Operation};
+use crate::decimal::DecimalOptions;
+use crate::function::Function;
+use crate::limits::Fuel;
 use crate::parse::Parser;
+use crate::vm::Program;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::sync::Arc;
 
-mod ast;
+pub use crate::bigint::BigInt;
+pub use crate::complex::Complex;
+pub use crate::context::Context;
+pub use crate::decimal::{Decimal, RoundingMode};
+pub use crate::diagnostic::Diagnostic;
+pub use crate::error::{Error, LimitError, ParseError, Result, ValueError};
+pub use crate::format::{Notation, Radix, ValueFormatter};
+pub use crate::function::Arity;
+pub use crate::limits::EvalLimits;
+pub use crate::rational::Rational;
+pub use crate::source::SourceFormatter;
+pub use crate::span::Span;
+
+pub mod ast;
+mod bigint;
+mod complex;
+mod context;
+mod decimal;
+mod diagnostic;
 mod error;
+mod format;
+mod function;
 mod lexer;
+mod limits;
 mod parse;
+mod rational;
+mod source;
+mod span;
+pub mod visit;
+mod vm;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
+    Boolean(bool),
     Integer(i64),
+    /// An integer outside the range of i64. Integer results that fit in an i64
+    /// are always returned as Value::Integer.
+    BigInteger(BigInt),
+    /// A fixed-point decimal, as written with a `d` suffix like `12.50d`
+    Decimal(Decimal),
+    /// An exact fraction, as produced by dividing integers. Rationals with a
+    /// denominator of 1 are always returned as integers.
+    Rational(Rational),
     Float(f64),
+    /// A complex number, as written with an `i` suffix like `3+4i`. Complex
+    /// results with a zero imaginary part are always returned as floats.
+    Complex(Complex),
+}
+
+/// The numeric types, in the order they are promoted to
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Rank {
+    Integer,
+    BigInteger,
+    Decimal,
+    Rational,
+    Float,
+    Complex,
+}
+
+impl Value {
+    /// Returns the numeric rank of the value, or None if it isn't a number
+    fn rank(&self) -> Option<Rank> {
+        match self {
+            Value::Integer(_) => Some(Rank::Integer),
+            Value::BigInteger(_) => Some(Rank::BigInteger),
+            Value::Decimal(_) => Some(Rank::Decimal),
+            Value::Rational(_) => Some(Rank::Rational),
+            Value::Float(_) => Some(Rank::Float),
+            Value::Complex(_) => Some(Rank::Complex),
+            _ => None,
+        }
+    }
+
+    /// Converts a number to the given rank if its own is lower, copying it
+    /// only then
+    fn promote_ref(&self, rank: Option<Rank>) -> Cow<'_, Value> {
+        match (self.rank(), rank) {
+            (Some(own), Some(rank)) if own < rank => Cow::Owned(self.clone().promote(rank)),
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    /// Converts a number to the given rank, which must not be lower than its own
+    fn promote(self, rank: Rank) -> Value {
+        match (self, rank) {
+            (Value::Integer(i), Rank::BigInteger) => Value::BigInteger(BigInt::from(i)),
+            (Value::Integer(i), Rank::Decimal) => Value::Decimal(Decimal::from(i)),
+            (Value::Integer(i), Rank::Rational) => Value::Rational(Rational::from(i)),
+            (Value::Integer(i), Rank::Float) => Value::Float(i as f64),
+            (Value::BigInteger(i), Rank::Decimal) => Value::Decimal(Decimal::from(i)),
+            (Value::BigInteger(i), Rank::Rational) => Value::Rational(Rational::from(i)),
+            (Value::BigInteger(i), Rank::Float) => Value::Float(i.to_f64()),
+            (Value::Decimal(d), Rank::Rational) => Value::Rational(d.to_rational()),
+            (Value::Decimal(d), Rank::Float) => Value::Float(d.to_f64()),
+            (Value::Rational(r), Rank::Float) => Value::Float(r.to_f64()),
+            (Value::Float(f), Rank::Complex) => Value::Complex(Complex::from(f)),
+            (
+                value @ (Value::Integer(_)
+                | Value::BigInteger(_)
+                | Value::Decimal(_)
+                | Value::Rational(_)),
+                Rank::Complex,
+            ) => value.promote(Rank::Float).promote(Rank::Complex),
+            (value, _) => value,
+        }
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
+            Value::Boolean(b) => f.write_str(b.to_string().as_ref()),
             Value::Integer(i) => f.write_str(i.to_string().as_ref()),
+            Value::BigInteger(i) => f.write_str(i.to_string().as_ref()),
+            Value::Decimal(d) => d.fmt(f),
+            Value::Rational(r) => r.fmt(f),
             Value::Float(i) => f.write_str(i.to_string().as_ref()),
+            Value::Complex(c) => c.fmt(f),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Self {
+        Value::Integer(i)
+    }
+}
+
+/// Converts to Value::Integer if the value fits, or Value::BigInteger otherwise
+impl From<BigInt> for Value {
+    fn from(i: BigInt) -> Self {
+        match i.to_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::BigInteger(i),
+        }
+    }
+}
+
+impl From<Decimal> for Value {
+    fn from(d: Decimal) -> Self {
+        Value::Decimal(d)
+    }
+}
+
+/// Converts to an integer value if the denominator is 1
+impl From<Rational> for Value {
+    fn from(r: Rational) -> Self {
+        if r.is_integer() {
+            r.numer().clone().into()
+        } else {
+            Value::Rational(r)
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+
+/// Converts to a float value if the imaginary part is zero
+impl From<Complex> for Value {
+    fn from(c: Complex) -> Self {
+        if c.im == 0.0 {
+            Value::Float(c.re)
+        } else {
+            Value::Complex(c)
         }
     }
 }
@@ -26,136 +192,1144 @@ impl Display for Value {
 impl From<ast::Literal> for Value {
     fn from(literal: Literal) -> Self {
         match literal {
+            Literal::Boolean(boolean) => Value::Boolean(boolean),
             Literal::Integer(integer) => Value::Integer(integer),
+            Literal::BigInteger(integer) => Value::BigInteger(integer),
+            Literal::Decimal(decimal) => Value::Decimal(decimal),
             Literal::Float(float) => Value::Float(float),
+            Literal::Imaginary(im) => Value::Complex(Complex::new(0.0, im)),
         }
     }
 }
 
 pub trait Calculate {
     fn calculate(&self) -> Result<Value>;
+    /// Calculates the value, resolving variables from the given context
+    fn calculate_with(&self, context: &Context) -> Result<Value>;
 }
 
 impl<T: AsRef<str>> Calculate for T {
     fn calculate(&self) -> Result<Value> {
-        Calculator::new(self.as_ref()).calculate()
+        self.calculate_with(&Context::default())
+    }
+
+    fn calculate_with(&self, context: &Context) -> Result<Value> {
+        Calculator::new().calculate_with(self.as_ref(), context)
     }
 }
 
-struct Calculator<'a> {
-    parser: Parser<'a>,
+/// How a [`Calculator`] evaluates expressions. Both give the same results and
+/// errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Walks the parsed expression recursively
+    #[default]
+    TreeWalk,
+    /// Compiles the expression to bytecode for a stack machine, which is
+    /// faster to evaluate repeatedly through [`CompiledExpression`]. It
+    /// parses, evaluates and drops expressions without recursion, so it can
+    /// calculate inputs of any depth, but the default [`EvalLimits`] still
+    /// reject inputs nested more than 128 deep or longer than 64 KiB; use
+    /// [`EvalLimits::unlimited`] for those.
+    Bytecode,
 }
 
-impl Calculator<'_> {
-    pub fn new(input: &str) -> Calculator {
-        Calculator {
-            parser: Parser::new(input),
-        }
-    }
-    pub fn calculate(&mut self) -> Result<Value> {
-        let expr = self.parser.parse()?;
-        Self::calculate_expression(expr)
-    }
-
-    fn calculate_expression(expression: Expression) -> Result<Value> {
-        Ok(match expression {
-            Expression::Literal(literal) => literal.into(),
-            Expression::Operation(operation) => match operation {
-                Operation::Add(lhs, rhs) => {
-                    match (
-                        Self::calculate_expression(*lhs)?,
-                        Self::calculate_expression(*rhs)?,
-                    ) {
-                        (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(
-                            lhs.checked_add(rhs)
-                                .ok_or(Error::Value("Integer overflow".into()))?,
-                        ),
-                        (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs + rhs),
-                        (Value::Integer(lhs), Value::Float(rhs)) => Value::Float(lhs as f64 + rhs),
-                        (Value::Float(lhs), Value::Integer(rhs)) => Value::Float(lhs + rhs as f64),
-                    }
+/// What an operation on two integers does when its result doesn't fit in an
+/// `i64`. Big integer operands are always calculated exactly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Fails with [`ValueError::IntegerOverflow`]
+    Error,
+    /// Wraps around in two's complement, like `i64::wrapping_add`
+    Wrap,
+    /// Clamps to `i64::MIN` or `i64::MAX`
+    Saturate,
+    /// Calculates the operation again with floats
+    PromoteToFloat,
+    /// Calculates the operation exactly with big integers
+    #[default]
+    PromoteToBigInt,
+}
+
+/// Evaluates expressions, calling any native functions registered through
+/// [`CalculatorBuilder`] in addition to the built-in ones.
+#[derive(Clone, Default)]
+pub struct Calculator {
+    functions: HashMap<String, Function>,
+    decimals: DecimalOptions,
+    backend: Backend,
+    limits: EvalLimits,
+    overflow: OverflowPolicy,
+}
+
+impl Calculator {
+    pub fn new() -> Calculator {
+        Calculator::default()
+    }
+
+    pub fn builder() -> CalculatorBuilder {
+        CalculatorBuilder::default()
+    }
+
+    pub fn calculate(&self, input: &str) -> Result<Value> {
+        self.calculate_with(input, &Context::default())
+    }
+
+    /// Calculates the value, resolving variables from the given context
+    pub fn calculate_with(&self, input: &str, context: &Context) -> Result<Value> {
+        let mut expression = self.parse(input)?;
+        let value = self.evaluate(&expression, context);
+        expression.drop_operands();
+        value
+    }
+
+    /// Parses the input into an expression with this calculator's options and
+    /// limits, without evaluating it
+    pub fn parse(&self, input: &str) -> Result<Expression> {
+        Parser::new(input)
+            .decimal_literals(self.decimals.literals)
+            .limits(self.limits)
+            .parse()
+    }
+
+    /// Parses the input once into an expression that can be evaluated many
+    /// times with this calculator's functions and options
+    pub fn compile(&self, input: &str) -> Result<CompiledExpression> {
+        let mut expression = self.parse(input)?;
+        let code = match self.backend {
+            Backend::TreeWalk => Code::Tree(expression),
+            Backend::Bytecode => {
+                let program = Program::compile(&expression);
+                expression.drop_operands();
+                Code::Bytecode(program)
+            }
+        };
+        Ok(CompiledExpression {
+            code,
+            calculator: self.clone(),
+        })
+    }
+
+    /// Calculates the value of the expression at the start of the input,
+    /// ignoring anything that follows it. Returns the value along with the
+    /// byte offset where the remaining input starts.
+    pub fn calculate_prefix(&self, input: &str, context: &Context) -> Result<(Value, usize)> {
+        let (mut expr, offset) = Parser::new(input)
+            .decimal_literals(self.decimals.literals)
+            .limits(self.limits)
+            .parse_prefix()?;
+        let value = self.evaluate(&expr, context);
+        expr.drop_operands();
+        Ok((value?, offset))
+    }
+
+    /// Evaluates a parsed expression with the configured backend, resolving
+    /// variables from the given context. The expression may have been built
+    /// or transformed rather than parsed, in which case it isn't checked
+    /// against the parsing limits.
+    pub fn evaluate(&self, expression: &Expression, context: &Context) -> Result<Value> {
+        match self.backend {
+            Backend::TreeWalk => {
+                self.calculate_expression(expression, context, &mut Fuel::new(&self.limits))
+            }
+            Backend::Bytecode => self.execute(&Program::compile(expression), context),
+        }
+    }
+
+    /// Calculates the value of an expression, locating any error without a
+    /// span at the expression.
+    fn calculate_expression(
+        &self,
+        expression: &Expression,
+        context: &Context,
+        fuel: &mut Fuel,
+    ) -> Result<Value> {
+        let value = match fuel.burn(1) {
+            Ok(()) => self.calculate_kind(&expression.kind, context, fuel),
+            Err(err) => Err(err),
+        };
+        value.map_err(|err| err.or_span(expression.span))
+    }
+
+    fn calculate_kind(
+        &self,
+        kind: &ExpressionKind,
+        context: &Context,
+        fuel: &mut Fuel,
+    ) -> Result<Value> {
+        match kind {
+            ExpressionKind::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.calculate_expression(arg, context, fuel))
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(name, args)
+            }
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                let branch = if self.calculate_boolean(condition, "branch on", context, fuel)? {
+                    then
+                } else {
+                    otherwise
+                };
+                self.calculate_expression(branch, context, fuel)
+            }
+            ExpressionKind::Literal(literal) => Ok(literal.clone().into()),
+            ExpressionKind::Variable(name) => Self::variable(name, context),
+            ExpressionKind::Operation(operation) => match operation {
+                Operation::And(lhs, rhs) => Ok(Value::Boolean(
+                    self.calculate_boolean(lhs, "apply and to", context, fuel)?
+                        && self.calculate_boolean(rhs, "apply and to", context, fuel)?,
+                )),
+                Operation::Assert(lhs) => self.calculate_expression(lhs, context, fuel),
+                Operation::Not(lhs) => Ok(Value::Boolean(!self.calculate_boolean(
+                    lhs,
+                    "apply not to",
+                    context,
+                    fuel,
+                )?)),
+                Operation::Or(lhs, rhs) => Ok(Value::Boolean(
+                    self.calculate_boolean(lhs, "apply or to", context, fuel)?
+                        || self.calculate_boolean(rhs, "apply or to", context, fuel)?,
+                )),
+                Operation::BitwiseNot(lhs) | Operation::Factorial(lhs) | Operation::Negate(lhs) => {
+                    let value = self.calculate_expression(lhs, context, fuel)?;
+                    self.calculate_unary(operation, value, fuel)
                 }
-                Operation::Assert(lhs) => Self::calculate_expression(*lhs)?,
-                Operation::Divide(lhs, rhs) => match (
-                    Self::calculate_expression(*lhs)?,
-                    Self::calculate_expression(*rhs)?,
-                ) {
-                    (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs - rhs),
-                    (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs - rhs),
-                    (Value::Integer(lhs), Value::Float(rhs)) => Value::Float(lhs as f64 - rhs),
-                    (Value::Float(lhs), Value::Integer(rhs)) => Value::Float(lhs - rhs as f64),
-                },
-                Operation::Exponentiate(lhs, rhs) => match (
-                    Self::calculate_expression(*lhs)?,
-                    Self::calculate_expression(*rhs)?,
-                ) {
-                    (Value::Integer(lhs), Value::Integer(rhs)) if rhs >= 0 => Value::Integer(
-                        lhs.checked_pow(rhs as u32)
-                            .ok_or(Error::Value("Integer overflow".into()))?,
-                    ),
-                    (Value::Integer(lhs), Value::Integer(rhs)) => {
-                        Value::Float((lhs as f64).powf(rhs as f64))
-                    }
-                    (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs.powf(rhs)),
-                    (Value::Integer(lhs), Value::Float(rhs)) => {
-                        Value::Float((lhs as f64).powf(rhs))
-                    }
-                    (Value::Float(lhs), Value::Integer(rhs)) => Value::Float(lhs.powf(rhs as f64)),
-                },
-                Operation::Factorial(lhs) => match Self::calculate_expression(*lhs)? {
-                    Value::Integer(i) if i < 0 => {
-                        return Err(Error::Value(
-                            "Can't take factorial of negative number".into(),
-                        ));
+                Operation::Add(lhs, rhs)
+                | Operation::BitwiseAnd(lhs, rhs)
+                | Operation::BitwiseOr(lhs, rhs)
+                | Operation::BitwiseXor(lhs, rhs)
+                | Operation::Divide(lhs, rhs)
+                | Operation::Equal(lhs, rhs)
+                | Operation::Exponentiate(lhs, rhs)
+                | Operation::GreaterThan(lhs, rhs)
+                | Operation::GreaterThanOrEqual(lhs, rhs)
+                | Operation::LessThan(lhs, rhs)
+                | Operation::LessThanOrEqual(lhs, rhs)
+                | Operation::Modulo(lhs, rhs)
+                | Operation::Multiply(lhs, rhs)
+                | Operation::NotEqual(lhs, rhs)
+                | Operation::ShiftLeft(lhs, rhs)
+                | Operation::ShiftRight(lhs, rhs)
+                | Operation::Subtract(lhs, rhs) => {
+                    let lhs = self.calculate_expression(lhs, context, fuel)?;
+                    let rhs = self.calculate_expression(rhs, context, fuel)?;
+                    self.calculate_binary(operation, lhs, rhs, fuel)
+                }
+            },
+        }
+    }
+
+    /// Applies a unary operation to its calculated operand. This is kept out
+    /// of [`Calculator::calculate_kind`], so that its locals don't grow the
+    /// frame of every level of recursion.
+    fn calculate_unary(
+        &self,
+        operation: &Operation,
+        value: Value,
+        fuel: &mut Fuel,
+    ) -> Result<Value> {
+        match operation {
+            Operation::BitwiseNot(_) => self.bitwise_not(value, fuel),
+            Operation::Factorial(_) => self.factorial(value, fuel),
+            Operation::Negate(_) => self.negate(value),
+            _ => unreachable!("not a unary operation"),
+        }
+    }
+
+    /// Applies a binary operation to its calculated operands, out of
+    /// [`Calculator::calculate_kind`] like [`Calculator::calculate_unary`]
+    fn calculate_binary(
+        &self,
+        operation: &Operation,
+        lhs: Value,
+        rhs: Value,
+        fuel: &mut Fuel,
+    ) -> Result<Value> {
+        match operation {
+            Operation::Add(..) => self.add(lhs, rhs, fuel),
+            Operation::BitwiseAnd(..) => self.bitwise_and(lhs, rhs, fuel),
+            Operation::BitwiseOr(..) => self.bitwise_or(lhs, rhs, fuel),
+            Operation::BitwiseXor(..) => self.bitwise_xor(lhs, rhs, fuel),
+            Operation::Divide(..) => self.divide(lhs, rhs, fuel),
+            Operation::Equal(..) => Self::equal(lhs, rhs),
+            Operation::Exponentiate(..) => self.exponentiate(lhs, rhs, fuel),
+            Operation::GreaterThan(..) => Self::greater_than(lhs, rhs),
+            Operation::GreaterThanOrEqual(..) => Self::greater_than_or_equal(lhs, rhs),
+            Operation::LessThan(..) => Self::less_than(lhs, rhs),
+            Operation::LessThanOrEqual(..) => Self::less_than_or_equal(lhs, rhs),
+            Operation::Modulo(..) => self.modulo(lhs, rhs, fuel),
+            Operation::Multiply(..) => self.multiply(lhs, rhs, fuel),
+            Operation::NotEqual(..) => Self::not_equal(lhs, rhs),
+            Operation::ShiftLeft(..) => self.shift_left(lhs, rhs, fuel),
+            Operation::ShiftRight(..) => self.shift_right(lhs, rhs),
+            Operation::Subtract(..) => self.subtract(lhs, rhs, fuel),
+            _ => unreachable!("not a binary operation"),
+        }
+    }
+
+    /// Calculates the value of an operand of a logical operation, failing at
+    /// the operand if it isn't a boolean
+    fn calculate_boolean(
+        &self,
+        expression: &Expression,
+        operation: &'static str,
+        context: &Context,
+        fuel: &mut Fuel,
+    ) -> Result<bool> {
+        let span = expression.span;
+        match self.calculate_expression(expression, context, fuel)? {
+            Value::Boolean(b) => Ok(b),
+            value => Err(Error::Value(
+                ValueError::InvalidOperand { operation, value },
+                Some(span),
+            )),
+        }
+    }
+
+    /// Calls a registered function, or else the built-in with the name
+    fn call(&self, name: &str, args: Vec<Value>) -> Result<Value> {
+        match self.functions.get(name) {
+            Some(function) => function.call(name, &args),
+            None => function::call(name, args, self.decimals.rounding),
+        }
+    }
+
+    fn variable(name: &str, context: &Context) -> Result<Value> {
+        Ok(context
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ValueError::UnknownVariable(name.into()))?)
+    }
+
+    fn add(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => match lhs.checked_add(rhs) {
+                Some(sum) => Value::Integer(sum),
+                None => self.overflow(lhs.wrapping_add(rhs), lhs as f64 + rhs as f64, || {
+                    Ok((BigInt::from(lhs) + BigInt::from(rhs)).into())
+                })?,
+            },
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_linear(lhs.bits().max(rhs.bits()), fuel)?;
+                (lhs + rhs).into()
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => {
+                Self::burn_linear(lhs.mantissa().bits().max(rhs.mantissa().bits()), fuel)?;
+                Value::Decimal(&lhs + &rhs)
+            }
+            (Value::Rational(lhs), Value::Rational(rhs)) => {
+                let numer = (lhs.numer().bits() + rhs.denom().bits())
+                    .max(rhs.numer().bits() + lhs.denom().bits());
+                Self::burn_division(numer, lhs.denom().bits() + rhs.denom().bits(), fuel)?;
+                (&lhs + &rhs).into()
+            }
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs + rhs),
+            (Value::Complex(lhs), Value::Complex(rhs)) => (lhs + rhs).into(),
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "add",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn bitwise_and(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Value::Boolean(lhs & rhs),
+            (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs & rhs),
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_linear(lhs.bits().max(rhs.bits()), fuel)?;
+                (&lhs & &rhs).into()
+            }
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "bitwise and",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn bitwise_not(&self, value: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match value {
+            Value::Integer(i) => Value::Integer(!i),
+            Value::BigInteger(i) => {
+                Self::burn_linear(i.bits(), fuel)?;
+                (!i).into()
+            }
+            other => {
+                return Err(ValueError::InvalidOperand {
+                    operation: "invert",
+                    value: other,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn bitwise_or(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Value::Boolean(lhs | rhs),
+            (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs | rhs),
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_linear(lhs.bits().max(rhs.bits()), fuel)?;
+                (&lhs | &rhs).into()
+            }
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "bitwise or",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn bitwise_xor(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Value::Boolean(lhs ^ rhs),
+            (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs ^ rhs),
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_linear(lhs.bits().max(rhs.bits()), fuel)?;
+                (&lhs ^ &rhs).into()
+            }
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "xor",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn divide(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(_), Value::Integer(0)) => {
+                return Err(ValueError::DivisionByZero.into());
+            }
+            // Only i64::MIN / -1 overflows
+            (Value::Integer(i64::MIN), Value::Integer(-1)) => {
+                self.overflow(i64::MIN, -(i64::MIN as f64), || {
+                    Ok((-BigInt::from(i64::MIN)).into())
+                })?
+            }
+            (Value::Integer(lhs), Value::Integer(rhs)) if lhs % rhs == 0 => {
+                Value::Integer(lhs / rhs)
+            }
+            (Value::Integer(lhs), Value::Integer(rhs)) => {
+                Rational::new(BigInt::from(lhs), BigInt::from(rhs))
+                    .ok_or(ValueError::DivisionByZero)?
+                    .into()
+            }
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_division(lhs.bits(), rhs.bits(), fuel)?;
+                Rational::new(lhs, rhs)
+                    .ok_or(ValueError::DivisionByZero)?
+                    .into()
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => {
+                let (lhs, rhs) = (lhs.to_rational(), rhs.to_rational());
+                Self::burn_rational_division(&lhs, &rhs, fuel)?;
+                let quot = (&lhs / &rhs).ok_or(ValueError::DivisionByZero)?;
+                Value::Decimal(Decimal::from_rational(
+                    &quot,
+                    self.decimals.scale,
+                    self.decimals.rounding,
+                ))
+            }
+            (Value::Rational(lhs), Value::Rational(rhs)) => {
+                Self::burn_rational_division(&lhs, &rhs, fuel)?;
+                (&lhs / &rhs).ok_or(ValueError::DivisionByZero)?.into()
+            }
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs / rhs),
+            (Value::Complex(lhs), Value::Complex(rhs)) => (lhs / rhs).into(),
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "divide",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn equal(lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(Value::Boolean(
+            Self::compare(&lhs, &rhs)? == Some(Ordering::Equal),
+        ))
+    }
+
+    fn exponentiate(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) if rhs >= 0 => {
+                match overflowing_pow(lhs, rhs.unsigned_abs()) {
+                    (result, false) => Value::Integer(result),
+                    (wrapped, true) => {
+                        // Large exponents are rounded to even floats, so the
+                        // sign is taken from the exponent itself
+                        let magnitude = (lhs.unsigned_abs() as f64).powf(rhs as f64);
+                        let float = if lhs < 0 && rhs % 2 == 1 {
+                            -magnitude
+                        } else {
+                            magnitude
+                        };
+                        self.overflow(wrapped, float, || {
+                            let rhs =
+                                u32::try_from(rhs).map_err(|_| ValueError::IntegerOverflow)?;
+                            let lhs = BigInt::from(lhs);
+                            self.check_power(rhs.into(), lhs.bits(), fuel)?;
+                            Ok(lhs.pow(rhs).into())
+                        })?
                     }
-                    Value::Integer(i) => Value::Integer((1..=i).product()),
-                    other => {
-                        return Err(Error::Value(format!("Can't take factorial of {}", other)));
+                }
+            }
+            (Value::Integer(0), Value::Integer(_)) => {
+                return Err(ValueError::DivisionByZero.into());
+            }
+            (Value::Integer(lhs), Value::Integer(rhs)) => {
+                let lhs = Rational::from(lhs);
+                self.check_power(rhs.unsigned_abs(), lhs.numer().bits(), fuel)?;
+                lhs.pow(rhs).ok_or(ValueError::IntegerOverflow)?.into()
+            }
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) if rhs.is_negative() => {
+                match rhs.to_i64() {
+                    Some(rhs) => {
+                        self.check_power(rhs.unsigned_abs(), lhs.bits(), fuel)?;
+                        Rational::from(lhs)
+                            .pow(rhs)
+                            .ok_or(ValueError::IntegerOverflow)?
+                            .into()
                     }
-                },
-                Operation::Modulo(lhs, rhs) => match (
-                    Self::calculate_expression(*lhs)?,
-                    Self::calculate_expression(*rhs)?,
-                ) {
-                    (Value::Integer(_), Value::Integer(0)) => {
-                        return Err(Error::Value("Can't divide by zero".into()));
+                    None => Value::Float(lhs.to_f64().powf(rhs.to_f64())),
+                }
+            }
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                match rhs.to_i64().and_then(|rhs| u32::try_from(rhs).ok()) {
+                    Some(rhs) => {
+                        self.check_power(rhs.into(), lhs.bits(), fuel)?;
+                        lhs.pow(rhs).into()
                     }
-                    (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs % rhs),
-                    (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs % rhs),
-                    (Value::Integer(lhs), Value::Float(rhs)) => Value::Float(lhs as f64 % rhs),
-                    (Value::Float(lhs), Value::Integer(rhs)) => Value::Float(lhs % rhs as f64),
-                },
-                Operation::Multiply(lhs, rhs) => match (
-                    Self::calculate_expression(*lhs)?,
-                    Self::calculate_expression(*rhs)?,
-                ) {
-                    (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(
-                        lhs.checked_mul(rhs)
-                            .ok_or(Error::Value("Integer overflow".into()))?,
+                    None => return Err(ValueError::IntegerOverflow.into()),
+                }
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) if rhs.to_rational().is_integer() => {
+                let scale = self.decimals.scale;
+                let exponent = rhs.to_rational().trunc().to_i64();
+                if let Some(exponent) = exponent {
+                    let bits = lhs.mantissa().bits();
+                    self.check_power(exponent.unsigned_abs(), bits, fuel)?;
+                }
+                match exponent.map(u32::try_from) {
+                    Some(Ok(exponent)) => Value::Decimal(
+                        lhs.pow(exponent)
+                            .ok_or(ValueError::IntegerOverflow)?
+                            .round(scale, self.decimals.rounding),
                     ),
-                    (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs * rhs),
-                    (Value::Integer(lhs), Value::Float(rhs)) => Value::Float(lhs as f64 * rhs),
-                    (Value::Float(lhs), Value::Integer(rhs)) => Value::Float(lhs * rhs as f64),
-                },
-                Operation::Negate(lhs) => match Self::calculate_expression(*lhs)? {
-                    Value::Integer(i) => Value::Integer(-i),
-                    Value::Float(f) => Value::Float(-f),
-                },
-                Operation::Subtract(lhs, rhs) => match (
-                    Self::calculate_expression(*lhs)?,
-                    Self::calculate_expression(*rhs)?,
-                ) {
-                    (Value::Integer(_), Value::Integer(0)) => {
-                        return Err(Error::Value("Can't divide by zero".into()));
+                    _ if lhs.is_zero() => return Err(ValueError::DivisionByZero.into()),
+                    _ => Value::Decimal(Decimal::from_rational(
+                        &exponent
+                            .and_then(|exponent| lhs.to_rational().pow(exponent))
+                            .ok_or(ValueError::IntegerOverflow)?,
+                        scale,
+                        self.decimals.rounding,
+                    )),
+                }
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => Self::powf(lhs.to_f64(), rhs.to_f64()),
+            (Value::Rational(lhs), Value::Rational(rhs)) if rhs.is_integer() => {
+                if let Some(exponent) = rhs.numer().to_i64() {
+                    let exponent = exponent.unsigned_abs();
+                    let bits = lhs.numer().bits() + lhs.denom().bits();
+                    self.check_power(exponent, bits, fuel)?;
+                    Self::burn_division(
+                        lhs.numer().bits().saturating_mul(exponent),
+                        lhs.denom().bits().saturating_mul(exponent),
+                        fuel,
+                    )?;
+                }
+                match rhs.numer().to_i64().and_then(|rhs| lhs.pow(rhs)) {
+                    Some(result) => result.into(),
+                    None => return Err(ValueError::IntegerOverflow.into()),
+                }
+            }
+            (Value::Rational(lhs), Value::Rational(rhs)) => Self::powf(lhs.to_f64(), rhs.to_f64()),
+            (Value::Float(lhs), Value::Float(rhs)) => Self::powf(lhs, rhs),
+            (Value::Complex(lhs), Value::Complex(rhs)) => lhs.powc(rhs).into(),
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "exponentiate",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    /// Handles an operation on two integers that overflowed according to the
+    /// overflow policy, given its wrapped result, its result calculated with
+    /// floats, and a way to calculate it exactly
+    fn overflow<F>(&self, wrapped: i64, float: f64, exact: F) -> Result<Value>
+    where
+        F: FnOnce() -> Result<Value>,
+    {
+        Ok(match self.overflow {
+            OverflowPolicy::Error => return Err(ValueError::IntegerOverflow.into()),
+            OverflowPolicy::Wrap => Value::Integer(wrapped),
+            // The float result has the sign of the exact result, even if it
+            // overflowed to infinity
+            OverflowPolicy::Saturate if float < 0.0 => Value::Integer(i64::MIN),
+            OverflowPolicy::Saturate => Value::Integer(i64::MAX),
+            OverflowPolicy::PromoteToFloat => Value::Float(float),
+            OverflowPolicy::PromoteToBigInt => exact()?,
+        })
+    }
+
+    /// Checks the exponent of an exact power against the limits, and takes a
+    /// step for every 64 bits of the result, given the bits of the base
+    fn check_exponent(&self, exponent: u64, bits: u64, fuel: &mut Fuel) -> Result<()> {
+        if exponent > self.limits.max_exponent {
+            let max = self.limits.max_exponent;
+            return Err(LimitError::ExponentTooLarge { max }.into());
+        }
+        fuel.burn(bits.saturating_mul(exponent) / 64)
+    }
+
+    /// Checks an exact power like [`Calculator::check_exponent`], and takes
+    /// the steps of the multiplications that compute it: squaring the base
+    /// takes time quadratic in its size, so they take about half a step for
+    /// every pair of 64-bit words of the result
+    fn check_power(&self, exponent: u64, bits: u64, fuel: &mut Fuel) -> Result<()> {
+        self.check_exponent(exponent, bits, fuel)?;
+        let words = bits.saturating_mul(exponent).div_ceil(64);
+        fuel.burn(words.saturating_mul(words) / 2)
+    }
+
+    /// Takes a step for every 64 bits of an operand of an operation that
+    /// takes linear time, like adding big integers
+    fn burn_linear(bits: u64, fuel: &mut Fuel) -> Result<()> {
+        fuel.burn(bits / 64)
+    }
+
+    /// Takes the steps to multiply integers with the given numbers of bits,
+    /// one for every pair of their 64-bit words
+    fn burn_multiplication(lhs_bits: u64, rhs_bits: u64, fuel: &mut Fuel) -> Result<()> {
+        fuel.burn(lhs_bits.div_ceil(64).saturating_mul(rhs_bits.div_ceil(64)))
+    }
+
+    /// Takes the steps to divide integers, or to normalise a rational, with
+    /// the given numbers of bits: long division and Euclid's algorithm take
+    /// time proportional to the product of the sizes of the operands
+    fn burn_division(lhs_bits: u64, rhs_bits: u64, fuel: &mut Fuel) -> Result<()> {
+        fuel.burn(lhs_bits.saturating_mul(rhs_bits) / 128)
+    }
+
+    /// Takes the steps to divide the rationals and normalise the quotient
+    fn burn_rational_division(lhs: &Rational, rhs: &Rational, fuel: &mut Fuel) -> Result<()> {
+        let numer = lhs.numer().bits() + rhs.denom().bits();
+        Self::burn_division(numer, lhs.denom().bits() + rhs.numer().bits(), fuel)
+    }
+
+    fn factorial(&self, value: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match value {
+            Value::Integer(i) if i < 0 => {
+                return Err(ValueError::NegativeFactorial.into());
+            }
+            Value::Integer(i) if i.unsigned_abs() > self.limits.max_factorial => {
+                let max = self.limits.max_factorial;
+                return Err(LimitError::FactorialTooLarge { max }.into());
+            }
+            Value::Integer(i) => {
+                // The product has about log2(i) bits for every factor, and
+                // each factor multiplies a product of half its size on average
+                let n = i.unsigned_abs();
+                let bits = n * u64::from(n.checked_ilog2().unwrap_or(0) + 1);
+                fuel.burn((bits / 64).saturating_mul(n) / 2)?;
+                match (2..=i).try_fold(1i64, i64::checked_mul) {
+                    Some(product) => Value::Integer(product),
+                    None => self.overflow(
+                        (2..=i).fold(1, i64::wrapping_mul),
+                        (2..=i).fold(1.0, |product, n| product * n as f64),
+                        || {
+                            Ok((2..=i)
+                                .fold(BigInt::from(1i64), |p, n| p * BigInt::from(n))
+                                .into())
+                        },
+                    )?,
+                }
+            }
+            Value::BigInteger(i) if i.is_negative() => {
+                return Err(ValueError::NegativeFactorial.into());
+            }
+            Value::BigInteger(_) => return Err(ValueError::IntegerOverflow.into()),
+            other => {
+                return Err(ValueError::InvalidOperand {
+                    operation: "take factorial of",
+                    value: other,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn greater_than(lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(Value::Boolean(
+            Self::order(lhs, rhs)? == Some(Ordering::Greater),
+        ))
+    }
+
+    fn greater_than_or_equal(lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(Value::Boolean(matches!(
+            Self::order(lhs, rhs)?,
+            Some(Ordering::Greater | Ordering::Equal)
+        )))
+    }
+
+    fn less_than(lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(Value::Boolean(
+            Self::order(lhs, rhs)? == Some(Ordering::Less),
+        ))
+    }
+
+    fn less_than_or_equal(lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(Value::Boolean(matches!(
+            Self::order(lhs, rhs)?,
+            Some(Ordering::Less | Ordering::Equal)
+        )))
+    }
+
+    fn modulo(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(_), Value::Integer(0)) => {
+                return Err(ValueError::DivisionByZero.into());
+            }
+            // Only i64::MIN % -1 overflows, and the remainder is 0
+            (Value::Integer(lhs), Value::Integer(rhs)) => {
+                Value::Integer(lhs.checked_rem(rhs).unwrap_or(0))
+            }
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_division(lhs.bits(), rhs.bits(), fuel)?;
+                let (_, rem) = lhs.div_rem(&rhs).ok_or(ValueError::DivisionByZero)?;
+                rem.into()
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => {
+                Value::Decimal(lhs.rem(&rhs).ok_or(ValueError::DivisionByZero)?)
+            }
+            (Value::Rational(lhs), Value::Rational(rhs)) => {
+                Self::burn_rational_division(&lhs, &rhs, fuel)?;
+                lhs.rem(&rhs).ok_or(ValueError::DivisionByZero)?.into()
+            }
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs % rhs),
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "modulo",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn multiply(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => match lhs.checked_mul(rhs) {
+                Some(product) => Value::Integer(product),
+                None => self.overflow(lhs.wrapping_mul(rhs), lhs as f64 * rhs as f64, || {
+                    Ok((BigInt::from(lhs) * BigInt::from(rhs)).into())
+                })?,
+            },
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_multiplication(lhs.bits(), rhs.bits(), fuel)?;
+                (lhs * rhs).into()
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => {
+                Self::burn_multiplication(lhs.mantissa().bits(), rhs.mantissa().bits(), fuel)?;
+                Value::Decimal((&lhs * &rhs).round(self.decimals.scale, self.decimals.rounding))
+            }
+            (Value::Rational(lhs), Value::Rational(rhs)) => {
+                let numer = lhs.numer().bits() + rhs.numer().bits();
+                Self::burn_division(numer, lhs.denom().bits() + rhs.denom().bits(), fuel)?;
+                (&lhs * &rhs).into()
+            }
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs * rhs),
+            (Value::Complex(lhs), Value::Complex(rhs)) => (lhs * rhs).into(),
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "multiply",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn negate(&self, value: Value) -> Result<Value> {
+        Ok(match value {
+            Value::Integer(i) => match i.checked_neg() {
+                Some(negated) => Value::Integer(negated),
+                None => self.overflow(i.wrapping_neg(), -(i as f64), || {
+                    Ok((-BigInt::from(i)).into())
+                })?,
+            },
+            Value::BigInteger(i) => (-i).into(),
+            Value::Decimal(d) => Value::Decimal(-d),
+            Value::Rational(r) => Value::Rational(-r),
+            Value::Float(f) => Value::Float(-f),
+            Value::Complex(c) => Value::Complex(-c),
+            other => {
+                return Err(ValueError::InvalidOperand {
+                    operation: "negate",
+                    value: other,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn not_equal(lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(Value::Boolean(
+            Self::compare(&lhs, &rhs)? != Some(Ordering::Equal),
+        ))
+    }
+
+    fn shift_left(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs))
+                if (0..64).contains(&rhs) && (lhs << rhs) >> rhs == lhs =>
+            {
+                Value::Integer(lhs << rhs)
+            }
+            (Value::Integer(0), Value::Integer(rhs)) if rhs >= 0 => Value::Integer(0),
+            (Value::Integer(lhs), Value::Integer(rhs)) if rhs >= 0 => {
+                let wrapped = if rhs < 64 { lhs << rhs } else { 0 };
+                let float = lhs as f64 * 2f64.powf(rhs as f64);
+                self.overflow(wrapped, float, || {
+                    let rhs = u32::try_from(rhs).map_err(|_| ValueError::IntegerOverflow)?;
+                    // Shifting multiplies by a power of two, with as many bits
+                    self.check_exponent(rhs.into(), 1, fuel)?;
+                    Ok((&BigInt::from(lhs) << rhs).into())
+                })?
+            }
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) if !rhs.is_negative() => {
+                match rhs.to_i64().and_then(|rhs| u32::try_from(rhs).ok()) {
+                    Some(rhs) => {
+                        self.check_exponent(rhs.into(), 1, fuel)?;
+                        Self::burn_linear(lhs.bits(), fuel)?;
+                        (&lhs << rhs).into()
                     }
-                    (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs / rhs),
-                    (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs / rhs),
-                    (Value::Integer(lhs), Value::Float(rhs)) => Value::Float(lhs as f64 / rhs),
-                    (Value::Float(lhs), Value::Integer(rhs)) => Value::Float(lhs / rhs as f64),
-                },
+                    None => return Err(ValueError::IntegerOverflow.into()),
+                }
+            }
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "shift left",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn shift_right(&self, lhs: Value, rhs: Value) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) if rhs >= 0 => {
+                Value::Integer(lhs >> rhs.min(63))
+            }
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) if !rhs.is_negative() => {
+                let rhs = rhs.to_i64().and_then(|rhs| u32::try_from(rhs).ok());
+                (&lhs >> rhs.unwrap_or(u32::MAX)).into()
+            }
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "shift right",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
+        })
+    }
+
+    fn subtract(&self, lhs: Value, rhs: Value, fuel: &mut Fuel) -> Result<Value> {
+        Ok(match Self::coerce(lhs, rhs) {
+            (Value::Integer(lhs), Value::Integer(rhs)) => match lhs.checked_sub(rhs) {
+                Some(difference) => Value::Integer(difference),
+                None => self.overflow(lhs.wrapping_sub(rhs), lhs as f64 - rhs as f64, || {
+                    Ok((BigInt::from(lhs) - BigInt::from(rhs)).into())
+                })?,
             },
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => {
+                Self::burn_linear(lhs.bits().max(rhs.bits()), fuel)?;
+                (lhs - rhs).into()
+            }
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => {
+                Self::burn_linear(lhs.mantissa().bits().max(rhs.mantissa().bits()), fuel)?;
+                Value::Decimal(&lhs - &rhs)
+            }
+            (Value::Rational(lhs), Value::Rational(rhs)) => {
+                let numer = (lhs.numer().bits() + rhs.denom().bits())
+                    .max(rhs.numer().bits() + lhs.denom().bits());
+                Self::burn_division(numer, lhs.denom().bits() + rhs.denom().bits(), fuel)?;
+                (&lhs - &rhs).into()
+            }
+            (Value::Float(lhs), Value::Float(rhs)) => Value::Float(lhs - rhs),
+            (Value::Complex(lhs), Value::Complex(rhs)) => (lhs - rhs).into(),
+            (lhs, rhs) => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "subtract",
+                    lhs,
+                    rhs,
+                }
+                .into());
+            }
         })
     }
+
+    /// Promotes numeric operands to a common type, following the order of
+    /// [`Rank`]: e.g. an integer combined with a rational becomes a rational,
+    /// and floats are contagious. Other values are returned unchanged.
+    fn coerce(lhs: Value, rhs: Value) -> (Value, Value) {
+        match (lhs.rank(), rhs.rank()) {
+            (Some(l), Some(r)) if l != r => {
+                let rank = l.max(r);
+                (lhs.promote(rank), rhs.promote(rank))
+            }
+            _ => (lhs, rhs),
+        }
+    }
+
+    /// Raises a float to a float power, giving the principal complex root for
+    /// a negative base and a fractional exponent rather than NaN
+    fn powf(lhs: f64, rhs: f64) -> Value {
+        if lhs < 0.0 && rhs.fract() != 0.0 {
+            Complex::from(lhs).powc(Complex::from(rhs)).into()
+        } else {
+            Value::Float(lhs.powf(rhs))
+        }
+    }
+
+    /// Compares the values for `<`, `>`, `<=` or `>=`, failing for complex
+    /// numbers which have no order
+    fn order(lhs: Value, rhs: Value) -> Result<Option<Ordering>> {
+        if matches!(lhs, Value::Complex(_)) || matches!(rhs, Value::Complex(_)) {
+            return Err(ValueError::InvalidOperands {
+                operation: "order",
+                lhs,
+                rhs,
+            }
+            .into());
+        }
+        Self::compare(&lhs, &rhs)
+    }
+
+    /// Compares two values, returning None if they are unordered (e.g. NaN)
+    fn compare(lhs: &Value, rhs: &Value) -> Result<Option<Ordering>> {
+        let rank = lhs.rank().max(rhs.rank());
+        let (lhs, rhs) = (lhs.promote_ref(rank), rhs.promote_ref(rank));
+        Ok(match (&*lhs, &*rhs) {
+            (Value::Boolean(lhs), Value::Boolean(rhs)) => Some(lhs.cmp(rhs)),
+            (Value::Integer(lhs), Value::Integer(rhs)) => Some(lhs.cmp(rhs)),
+            (Value::BigInteger(lhs), Value::BigInteger(rhs)) => Some(lhs.cmp(rhs)),
+            (Value::Decimal(lhs), Value::Decimal(rhs)) => Some(lhs.cmp(rhs)),
+            (Value::Rational(lhs), Value::Rational(rhs)) => Some(lhs.cmp(rhs)),
+            (Value::Float(lhs), Value::Float(rhs)) => lhs.partial_cmp(rhs),
+            // Complex numbers are only compared for equality, see `order`
+            (Value::Complex(lhs), Value::Complex(rhs)) => (lhs == rhs).then_some(Ordering::Equal),
+            _ => {
+                return Err(ValueError::InvalidOperands {
+                    operation: "compare",
+                    lhs: lhs.into_owned(),
+                    rhs: rhs.into_owned(),
+                }
+                .into());
+            }
+        })
+    }
+}
+
+/// Raises an integer to a power by squaring, returning the wrapped result and
+/// whether it overflowed, like `i64::overflowing_pow` with a larger exponent
+fn overflowing_pow(mut base: i64, mut exponent: u64) -> (i64, bool) {
+    let (mut result, mut overflowed) = (1i64, false);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            let (product, o) = result.overflowing_mul(base);
+            (result, overflowed) = (product, overflowed || o);
+        }
+        exponent >>= 1;
+        // The base is only squared if it is used again, so the squares all
+        // divide the result and it overflows if any of them do
+        if exponent > 0 {
+            let (square, o) = base.overflowing_mul(base);
+            (base, overflowed) = (square, overflowed || o);
+        }
+    }
+    (result, overflowed)
+}
+
+/// Parses the input into an expression with the default [`Calculator`]'s
+/// options and limits, to inspect or transform it before evaluation:
+///
+/// ```
+/// use calculator_rs::ast::{ExpressionKind, Literal, Operation};
+///
+/// let expression = calculator_rs::parse("2 * 21").unwrap();
+/// match &expression.kind {
+///     ExpressionKind::Operation(Operation::Multiply(lhs, _)) => {
+///         assert_eq!(lhs.kind, ExpressionKind::Literal(Literal::Integer(2)))
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+pub fn parse(input: &str) -> Result<Expression> {
+    Calculator::new().parse(input)
+}
+
+/// An expression that has been parsed once, to be evaluated many times with
+/// different variable bindings without lexing and parsing it again:
+///
+/// ```
+/// use calculator_rs::{CompiledExpression, Context, Value};
+///
+/// let expression = CompiledExpression::new("qty * unit_price").unwrap();
+/// for qty in 1..=3 {
+///     let context = Context::new().with("qty", qty).with("unit_price", 2);
+///     assert_eq!(expression.eval(&context), Ok(Value::Integer(qty * 2)));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CompiledExpression {
+    code: Code,
+    calculator: Calculator,
+}
+
+/// What a [`CompiledExpression`] evaluates, depending on the backend
+#[derive(Clone)]
+enum Code {
+    Tree(Expression),
+    /// Only the bytecode is kept, as the expression may be nested too deeply
+    /// to drop recursively
+    Bytecode(Program),
+}
+
+impl CompiledExpression {
+    /// Parses the input for evaluation with the default [`Calculator`]. Use
+    /// [`Calculator::compile`] to evaluate it with native functions or other
+    /// options.
+    pub fn new(input: &str) -> Result<CompiledExpression> {
+        Calculator::new().compile(input)
+    }
+
+    /// Evaluates the expression, resolving variables from the given context
+    pub fn eval(&self, context: &Context) -> Result<Value> {
+        match &self.code {
+            Code::Bytecode(program) => self.calculator.execute(program, context),
+            Code::Tree(expression) => self.calculator.calculate_expression(
+                expression,
+                context,
+                &mut Fuel::new(&self.calculator.limits),
+            ),
+        }
+    }
+}
+
+/// Builds a [`Calculator`] with user-registered native functions and
+/// arithmetic options.
+#[derive(Clone, Default)]
+pub struct CalculatorBuilder {
+    functions: HashMap<String, Function>,
+    decimals: DecimalOptions,
+    backend: Backend,
+    limits: EvalLimits,
+    overflow: OverflowPolicy,
+}
+
+impl CalculatorBuilder {
+    /// Registers a native function, replacing any function (including a
+    /// built-in) with the same name. The arguments are checked against the
+    /// arity before the function is called.
+    pub fn function<N, A, F>(mut self, name: N, arity: A, func: F) -> CalculatorBuilder
+    where
+        N: Into<String>,
+        A: Into<Arity>,
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.functions.insert(
+            name.into(),
+            Function {
+                arity: arity.into(),
+                func: Arc::new(func),
+            },
+        );
+        self
+    }
+
+    /// Sets the number of fractional digits that decimal products, quotients
+    /// and powers are rounded to. Sums and differences are always exact.
+    /// Defaults to 2.
+    pub fn decimal_scale(mut self, scale: u32) -> CalculatorBuilder {
+        self.decimals.scale = scale;
+        self
+    }
+
+    /// Sets how decimal results, and decimals passed to `round` with a number
+    /// of digits, are rounded. Defaults to
+    /// [`RoundingMode::HalfEven`].
+    pub fn rounding_mode(mut self, rounding: RoundingMode) -> CalculatorBuilder {
+        self.decimals.rounding = rounding;
+        self
+    }
+
+    /// Parses number literals with a fractional part, like `0.1`, as decimals
+    /// rather than floats, as if they were written with a `d` suffix.
+    pub fn decimal_literals(mut self, enabled: bool) -> CalculatorBuilder {
+        self.decimals.literals = enabled;
+        self
+    }
+
+    /// Sets how expressions are evaluated. Defaults to
+    /// [`Backend::TreeWalk`].
+    pub fn backend(mut self, backend: Backend) -> CalculatorBuilder {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets the limits on input and evaluation, which default to
+    /// [`EvalLimits::default`]
+    pub fn limits(mut self, limits: EvalLimits) -> CalculatorBuilder {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets what integer operations do when they overflow. Defaults to
+    /// [`OverflowPolicy::PromoteToBigInt`].
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> CalculatorBuilder {
+        self.overflow = policy;
+        self
+    }
+
+    pub fn build(self) -> Calculator {
+        Calculator {
+            functions: self.functions,
+            decimals: self.decimals,
+            backend: self.backend,
+            limits: self.limits,
+            overflow: self.overflow,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -169,6 +1343,11 @@ mod tests {
             assert_eq!(calculator, Ok(Value::Integer(2)))
         }
 
+        {
+            let calculator = "1 + # comments are skipped\n2 # to the end of the line".calculate();
+            assert_eq!(calculator, Ok(Value::Integer(3)))
+        }
+
         {
             let calculator = "1*1".calculate();
             assert_eq!(calculator, Ok(Value::Integer(1)))
@@ -204,4 +1383,1056 @@ mod tests {
             assert_eq!(calculator, Ok(Value::Float(28.4)))
         }
     }
+
+    #[test]
+    fn test_big_integer() {
+        let big = |s: &str| Ok(Value::BigInteger(s.parse().unwrap()));
+        assert_eq!("30!".calculate(), big("265252859812191058636308480000000"));
+        assert_eq!(
+            "2^200".calculate(),
+            big("1606938044258990275541962092341162602522202993782792835301376")
+        );
+        assert_eq!(
+            "9223372036854775807 + 1".calculate(),
+            big("9223372036854775808")
+        );
+        assert_eq!(
+            "-9223372036854775807 - 2".calculate(),
+            big("-9223372036854775809")
+        );
+        assert_eq!(
+            "-(-9223372036854775807 - 1)".calculate(),
+            big("9223372036854775808")
+        );
+        assert_eq!(
+            "99999999999999999999".calculate(),
+            big("99999999999999999999")
+        );
+        assert_eq!(
+            "99999999999999999999 * 3.0".calculate(),
+            Ok(Value::Float(3e20))
+        );
+        assert_eq!("2^64 / 2^60".calculate(), Ok(Value::Integer(16)));
+        assert_eq!("2^64 - 2^64 + 1".calculate(), Ok(Value::Integer(1)));
+        assert_eq!("(2^64 + 5) % 2^32".calculate(), Ok(Value::Integer(5)));
+        assert_eq!("2^64 > 2^63".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("2^64 = 2^64".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("2^64 < 1.5".calculate(), Ok(Value::Boolean(false)));
+        assert_eq!(
+            "(-9223372036854775807 - 1) % -1".calculate(),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(
+            "abs(-9223372036854775807 - 1)".calculate(),
+            big("9223372036854775808")
+        );
+        assert_eq!("2^(2^64)".calculate().unwrap_err().code(), "E0103");
+        assert_eq!("(2^64) / 0".calculate().unwrap_err().code(), "E0102");
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let calculate = |policy, input: &str| {
+            Calculator::builder()
+                .overflow_policy(policy)
+                .build()
+                .calculate(input)
+        };
+        const MIN: &str = "(-9223372036854775807 - 1)";
+        let inputs = [
+            "9223372036854775807 + 1".to_string(),
+            "-9223372036854775807 - 2".to_string(),
+            "4294967296 * -4294967296".to_string(),
+            format!("-{}", MIN),
+            format!("{} / -1", MIN),
+            "3^41".to_string(),
+            "(-3)^41".to_string(),
+            "21!".to_string(),
+            "3 << 62".to_string(),
+        ];
+        let expected = [
+            (
+                OverflowPolicy::Wrap,
+                [
+                    "-9223372036854775808",
+                    "9223372036854775807",
+                    "0",
+                    "-9223372036854775808",
+                    "-9223372036854775808",
+                    "-420491770248316829",
+                    "420491770248316829",
+                    "-4249290049419214848",
+                    "-4611686018427387904",
+                ],
+            ),
+            (
+                OverflowPolicy::Saturate,
+                [
+                    "9223372036854775807",
+                    "-9223372036854775808",
+                    "-9223372036854775808",
+                    "9223372036854775807",
+                    "9223372036854775807",
+                    "9223372036854775807",
+                    "-9223372036854775808",
+                    "9223372036854775807",
+                    "9223372036854775807",
+                ],
+            ),
+            (
+                OverflowPolicy::PromoteToFloat,
+                [
+                    "9223372036854776000",
+                    "-9223372036854776000",
+                    "-18446744073709552000",
+                    "9223372036854776000",
+                    "9223372036854776000",
+                    "36472996377170790000",
+                    "-36472996377170790000",
+                    "51090942171709440000",
+                    "13835058055282164000",
+                ],
+            ),
+            (
+                OverflowPolicy::PromoteToBigInt,
+                [
+                    "9223372036854775808",
+                    "-9223372036854775809",
+                    "-18446744073709551616",
+                    "9223372036854775808",
+                    "9223372036854775808",
+                    "36472996377170786403",
+                    "-36472996377170786403",
+                    "51090942171709440000",
+                    "13835058055282163712",
+                ],
+            ),
+        ];
+        for (policy, results) in expected {
+            for (input, result) in inputs.iter().zip(results) {
+                let value = calculate(policy, input).unwrap();
+                assert_eq!(value.to_string(), result, "{} with {:?}", input, policy);
+            }
+        }
+        for input in &inputs {
+            assert_eq!(
+                calculate(OverflowPolicy::Error, input),
+                Err(Error::Value(
+                    ValueError::IntegerOverflow,
+                    Some(Span::new(0, input.len()))
+                ))
+            );
+        }
+        assert_eq!(
+            calculate(OverflowPolicy::PromoteToFloat, "2.0 * 3").unwrap(),
+            Value::Float(6.0)
+        );
+        assert_eq!(
+            calculate(OverflowPolicy::Error, "3^39 + 21 - 20!"),
+            Ok(Value::Integer(
+                4052555153018976267 - 2432902008176640000 + 21
+            ))
+        );
+        // Integers that fit aren't affected, however large the exponent or
+        // shift
+        for policy in [OverflowPolicy::Error, OverflowPolicy::Saturate] {
+            assert_eq!(calculate(policy, "1^(2^40)"), Ok(Value::Integer(1)));
+            assert_eq!(calculate(policy, "(-1)^(2^40 + 1)"), Ok(Value::Integer(-1)));
+            assert_eq!(calculate(policy, "0 << 100"), Ok(Value::Integer(0)));
+            assert_eq!(calculate(policy, "(-2)^63"), Ok(Value::Integer(i64::MIN)));
+        }
+        assert_eq!(
+            calculate(OverflowPolicy::Saturate, "(-2)^(2^60 + 1)"),
+            Ok(Value::Integer(i64::MIN))
+        );
+        assert_eq!(
+            calculate(OverflowPolicy::Wrap, "3^(2^62)"),
+            Ok(Value::Integer(
+                3i64.wrapping_pow(1 << 31).wrapping_pow(1 << 31)
+            ))
+        );
+    }
+
+    /// Generates inputs from a seeded xorshift generator, so failures can be
+    /// reproduced without a fuzzing dependency
+    struct Inputs(u64);
+
+    impl Inputs {
+        const ATOMS: &'static str = "0 1 2 7 63 64 9223372036854775807 9223372036854775808 \
+            1.5 0.1d 1e308 2i 0x7f x y true false";
+        const PREFIX: &'static str = "- + ~ not";
+        const INFIX: &'static str = "+ - * / % ^ << >> & | xor = != < >= and or";
+        const FUNCTIONS: &'static str = "abs max sqrt round log";
+
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn pick(&mut self, words: &'static str) -> &'static str {
+            let words: Vec<_> = words.split_whitespace().collect();
+            words[self.next() as usize % words.len()]
+        }
+
+        /// Generates a well-formed expression, which exercises evaluation
+        fn expression(&mut self, depth: u32) -> String {
+            match if depth == 0 { 0 } else { self.next() % 7 } {
+                1 => format!("{} {}", self.pick(Self::PREFIX), self.expression(depth - 1)),
+                2 => format!("({})", self.expression(depth - 1)),
+                3 => format!("{}!", self.expression(depth - 1)),
+                4 => format!(
+                    "{}({}, {})",
+                    self.pick(Self::FUNCTIONS),
+                    self.expression(depth - 1),
+                    self.expression(depth - 1)
+                ),
+                5 => format!(
+                    "{} ? {} : {}",
+                    self.expression(depth - 1),
+                    self.expression(depth - 1),
+                    self.expression(depth - 1)
+                ),
+                6 => format!(
+                    "{} {} {}",
+                    self.expression(depth - 1),
+                    self.pick(Self::INFIX),
+                    self.expression(depth - 1)
+                ),
+                _ => self.pick(Self::ATOMS).to_string(),
+            }
+        }
+
+        /// Generates a sequence of arbitrary tokens, which exercises parsing
+        fn tokens(&mut self) -> String {
+            let vocabulary = [Self::ATOMS, Self::PREFIX, Self::INFIX, "! ? : ( ) abs( ,"];
+            (0..self.next() % 12 + 1)
+                .map(|_| {
+                    let words = vocabulary[self.next() as usize % vocabulary.len()];
+                    self.pick(words)
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+
+    #[test]
+    fn test_no_panic() {
+        let policies = [
+            OverflowPolicy::Error,
+            OverflowPolicy::Wrap,
+            OverflowPolicy::Saturate,
+            OverflowPolicy::PromoteToFloat,
+            OverflowPolicy::PromoteToBigInt,
+        ];
+        let calculators: Vec<_> = policies
+            .iter()
+            .flat_map(|policy| {
+                [Backend::TreeWalk, Backend::Bytecode].map(|backend| {
+                    Calculator::builder()
+                        .overflow_policy(*policy)
+                        .backend(backend)
+                        .decimal_literals(backend == Backend::Bytecode)
+                        .build()
+                })
+            })
+            .collect();
+        let context = Context::new().with("x", i64::MIN).with("y", i64::MAX);
+        let mut inputs = Inputs(0x2545_f491_4f6c_dd1d);
+        for i in 0..4000 {
+            let input = match i % 2 {
+                0 => inputs.expression(4),
+                _ => inputs.tokens(),
+            };
+            for calculator in &calculators {
+                let _ = calculator.calculate_with(&input, &context);
+            }
+        }
+        // Arbitrary characters, which are mostly rejected by the lexer
+        for _ in 0..4000 {
+            let input: String = (0..inputs.next() % 16)
+                .map(|_| char::from(inputs.next() as u8 % 95 + 32))
+                .collect();
+            let _ = input.calculate_with(&context);
+        }
+    }
+
+    #[test]
+    fn test_display_expression() {
+        let print = |input: &str| parse(input).unwrap().to_string();
+        assert_eq!(print("(1+1)*2+4!"), "(1 + 1) * 2 + 4!");
+        assert_eq!(print("((a - b)) - (c - d)"), "a - b - (c - d)");
+        assert_eq!(print("2^3^4"), "2 ^ 3 ^ 4");
+        assert_eq!(print("(2^3)^4"), "(2 ^ 3) ^ 4");
+        assert_eq!(print("-2^2"), "-2 ^ 2");
+        assert_eq!(print("-(2^2)"), "-(2 ^ 2)");
+        assert_eq!(print("(-3)! + -(3!)"), "-3! + -(3!)");
+        assert_eq!(print("x+(not y)"), "x + (not y)");
+        assert_eq!(print("not(a=b)&&c||d"), "not a = b and c or d");
+        assert_eq!(print("a?b?c:d:(e?f:g)"), "a ? b ? c : d : e ? f : g");
+        assert_eq!(print("(a?b:c)?d:e"), "(a ? b : c) ? d : e");
+        assert_eq!(print("max(1,-x,f())"), "max(1, -x, f())");
+        assert_eq!(
+            print("0x_ff + 1e3 + 2.50d + 3i"),
+            "255 + 1000.0 + 2.50d + 3.0i"
+        );
+    }
+
+    /// Parses the input with its spans reset, for comparing expressions
+    /// that were written differently
+    fn parse_without_spans(input: &str) -> Result<Expression> {
+        struct ClearSpans;
+        impl visit::MutVisitor for ClearSpans {
+            fn visit_expression_mut(&mut self, expression: &mut Expression) {
+                expression.span = Span::default();
+                visit::walk_expression_mut(self, expression);
+            }
+        }
+        let mut expression = parse(input)?;
+        visit::MutVisitor::visit_expression_mut(&mut ClearSpans, &mut expression);
+        Ok(expression)
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let mut inputs = Inputs(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..4000 {
+            let expression = match parse_without_spans(&inputs.expression(5)) {
+                Ok(expression) => expression,
+                Err(_) => continue,
+            };
+            let printed = expression.to_string();
+            assert_eq!(parse_without_spans(&printed), Ok(expression), "{}", printed);
+        }
+        // Literals too large for a float would print as `inf`
+        for input in ["1e999", "1e999i", "-1e400 * 2"] {
+            assert_eq!(
+                parse_without_spans(input).unwrap_err().code(),
+                "E0006",
+                "{}",
+                input
+            );
+        }
+        assert_eq!(parse_without_spans("1e308").unwrap().to_string(), "1e308");
+    }
+
+    #[test]
+    fn test_source_formatter_round_trip() {
+        let formatters = [
+            SourceFormatter::new(),
+            SourceFormatter::new().keep_parentheses(true),
+            SourceFormatter::new().width(20),
+        ];
+        let mut inputs = Inputs(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let input = inputs.expression(5);
+            let expression = match parse_without_spans(&input) {
+                Ok(expression) => expression,
+                Err(_) => continue,
+            };
+            for formatter in &formatters {
+                let formatted = formatter.format(&input).unwrap();
+                assert_eq!(
+                    parse_without_spans(&formatted),
+                    Ok(expression.clone()),
+                    "{}",
+                    formatted
+                );
+                assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rational() {
+        let rational = |numer: i64, denom: i64| {
+            Ok(Value::Rational(
+                Rational::new(BigInt::from(numer), BigInt::from(denom)).unwrap(),
+            ))
+        };
+        assert_eq!("7 / 2".calculate(), rational(7, 2));
+        assert_eq!("6 / 3".calculate(), Ok(Value::Integer(2)));
+        assert_eq!("1 + 6 / 2".calculate(), Ok(Value::Integer(4)));
+        assert_eq!("1 - 2 * 3".calculate(), Ok(Value::Integer(-5)));
+        assert_eq!("1/10 + 2/10".calculate(), rational(3, 10));
+        assert_eq!("1/10 + 2/10 = 3/10".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("1/3 + 2/3".calculate(), Ok(Value::Integer(1)));
+        assert_eq!("1/3 - 1/2".calculate(), rational(-1, 6));
+        assert_eq!("(2/3) * (3/4)".calculate(), rational(1, 2));
+        assert_eq!("(2/3) / (4/3)".calculate(), rational(1, 2));
+        assert_eq!("-(1/2)".calculate(), rational(-1, 2));
+        assert_eq!("(2/3)^2".calculate(), rational(4, 9));
+        assert_eq!("(2/3)^-2".calculate(), rational(9, 4));
+        assert_eq!("2^-2".calculate(), rational(1, 4));
+        assert_eq!("(7/2) % 1".calculate(), rational(1, 2));
+        assert_eq!("1/2 + 0.5".calculate(), Ok(Value::Float(1.0)));
+        assert_eq!("1/3 < 0.34".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("1/3 > 1/4".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!(
+            "floor(7/2) + ceil(7/2) + round(-7/2)".calculate(),
+            Ok(Value::Integer(3))
+        );
+        assert_eq!("abs(-1/2)".calculate(), rational(1, 2));
+        assert_eq!("min(1/2, 1/3)".calculate(), rational(1, 3));
+        assert_eq!("1 / (1/2 - 1/2)".calculate().unwrap_err().code(), "E0102");
+        assert_eq!("0^-1".calculate().unwrap_err().code(), "E0102");
+        assert_eq!(
+            "(2^64 + 1) / 2^64".calculate(),
+            Ok(Value::Rational(
+                Rational::new(
+                    "18446744073709551617".parse().unwrap(),
+                    "18446744073709551616".parse().unwrap()
+                )
+                .unwrap()
+            ))
+        );
+        assert_eq!("1 / 3".calculate().unwrap().to_string(), "1/3");
+        assert_eq!(format!("{:.4}", "1 / 3".calculate().unwrap()), "0.3333");
+    }
+
+    #[test]
+    fn test_decimal() {
+        let decimal = |s: &str| Ok(Value::Decimal(s.parse().unwrap()));
+        assert_eq!("0.1d + 0.2d".calculate(), decimal("0.3"));
+        assert_eq!("0.1d + 0.2d = 0.3d".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("12.50d * 3".calculate().unwrap().to_string(), "37.50");
+        assert_eq!("10d / 3".calculate().unwrap().to_string(), "3.33");
+        assert_eq!("2.5d * 0.5d".calculate().unwrap().to_string(), "1.25");
+        assert_eq!("0.125d * 3".calculate().unwrap().to_string(), "0.38");
+        assert_eq!("1.005d * 1".calculate().unwrap().to_string(), "1.00");
+        assert_eq!("1.005d + 1".calculate().unwrap().to_string(), "2.005");
+        assert_eq!("1.5d ^ 2".calculate().unwrap().to_string(), "2.25");
+        assert_eq!("2d ^ -2".calculate().unwrap().to_string(), "0.25");
+        assert_eq!("-7.5d % 2".calculate(), decimal("-1.5"));
+        assert_eq!("1d / 0d".calculate().unwrap_err().code(), "E0102");
+        assert_eq!("0.5d + 1/4".calculate().unwrap().to_string(), "3/4");
+        assert_eq!("0.5d + 0.25".calculate(), Ok(Value::Float(0.75)));
+        assert_eq!("round(2.675d, 2)".calculate(), decimal("2.68"));
+        assert_eq!("round(2.665d, 2)".calculate(), decimal("2.66"));
+        assert_eq!("round(1250d, -2)".calculate(), decimal("1200"));
+        assert_eq!("round(1350.5d, -2)".calculate(), decimal("1400"));
+        let down = Calculator::builder()
+            .rounding_mode(RoundingMode::Down)
+            .build();
+        assert_eq!(down.calculate("round(2.679d, 2)"), decimal("2.67"));
+        assert_eq!(down.calculate("round(-1299d, -2)"), decimal("-1200"));
+        assert_eq!("floor(-2.5d)".calculate(), Ok(Value::Integer(-3)));
+        assert_eq!("abs(-2.50d)".calculate().unwrap().to_string(), "2.50");
+        assert!("2days".calculate().is_err());
+
+        let calculator = Calculator::builder()
+            .decimal_literals(true)
+            .decimal_scale(4)
+            .rounding_mode(RoundingMode::Down)
+            .build();
+        assert_eq!(calculator.calculate("0.1 + 0.2"), decimal("0.3"));
+        assert_eq!(
+            calculator.calculate("2 / 3.0").unwrap().to_string(),
+            "0.6666"
+        );
+        assert_eq!(calculator.calculate("1 / 3"), "1/3".calculate());
+
+        let calculator = Calculator::builder()
+            .rounding_mode(RoundingMode::HalfUp)
+            .build();
+        assert_eq!(calculator.calculate("0.25d * 0.5d"), decimal("0.13"));
+        assert_eq!("0.25d * 0.5d".calculate(), decimal("0.12"));
+    }
+
+    #[test]
+    fn test_complex() {
+        let complex = |re, im| Ok(Value::Complex(Complex::new(re, im)));
+        assert_eq!("3+4i".calculate(), complex(3.0, 4.0));
+        assert_eq!("(1+2i) * (3-1i)".calculate(), complex(5.0, 5.0));
+        assert_eq!("(1+2i) / (3+4i)".calculate(), complex(0.44, 0.08));
+        assert_eq!("1i * 1i".calculate(), Ok(Value::Float(-1.0)));
+        assert_eq!("2i^2".calculate(), Ok(Value::Float(-4.0)));
+        assert_eq!("-(1 - 1i)".calculate(), complex(-1.0, 1.0));
+        assert_eq!("sqrt(-1)".calculate(), complex(0.0, 1.0));
+        assert_eq!("sqrt(-4) = 2i".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("sqrt(-3+4i)".calculate(), complex(1.0, 2.0));
+        assert_eq!("abs(3-4i)".calculate(), Ok(Value::Float(5.0)));
+        assert_eq!(
+            "ln(1i)".calculate(),
+            complex(0.0, std::f64::consts::FRAC_PI_2)
+        );
+        assert_eq!("1/2 + 1i".calculate(), complex(0.5, 1.0));
+        match "(-8)^(1/3)".calculate() {
+            Ok(Value::Complex(c)) => {
+                assert!((c.re - 1.0).abs() < 1e-12);
+                assert!((c.im - 3f64.sqrt()).abs() < 1e-12);
+            }
+            other => panic!("expected a complex root, got {:?}", other),
+        }
+        assert_eq!("(-8)^2".calculate(), Ok(Value::Integer(64)));
+        assert_eq!("1i < 2i".calculate().unwrap_err().code(), "E0104");
+        assert_eq!("1 >= 2i".calculate().unwrap_err().code(), "E0104");
+        assert_eq!("1i = 1i".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("1i != 2i".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("0i^-1".calculate().unwrap().to_string(), "NaN+NaNi");
+        assert_eq!("(1+1i) % 2".calculate().unwrap_err().code(), "E0104");
+        assert_eq!("cos(1i)".calculate().unwrap_err().code(), "E0109");
+
+        let value = "3+4i".calculate().unwrap();
+        assert_eq!(value.to_string(), "3+4i");
+        assert_eq!(format!("{:#.4}", value), "5.0000∠0.9273");
+        assert_eq!("1 - 0.5i".calculate().unwrap().to_string(), "1-0.5i");
+    }
+
+    #[test]
+    fn test_scientific_notation() {
+        assert_eq!("6.022e23".calculate(), Ok(Value::Float(6.022e23)));
+        assert_eq!("1E-9 * 2".calculate(), Ok(Value::Float(2e-9)));
+        assert_eq!("1e3".calculate(), Ok(Value::Float(1000.0)));
+        assert_eq!(".5 + .25".calculate(), Ok(Value::Float(0.75)));
+        assert_eq!("1.5e3d".calculate().unwrap().to_string(), "1500");
+        assert_eq!("2.5e-1d".calculate().unwrap().to_string(), "0.25");
+        assert_eq!(
+            "1e1i".calculate(),
+            Ok(Value::Complex(Complex::new(0.0, 10.0)))
+        );
+        assert_eq!("1e".calculate().unwrap_err().code(), "E0007");
+        assert_eq!(
+            "1.2.3".calculate().unwrap_err().to_string(),
+            "Malformed number 1.2.3: a number can only have one decimal point"
+        );
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        assert_eq!("0xFF_FF".calculate(), Ok(Value::Integer(0xFFFF)));
+        assert_eq!("0b1010 + 0o755".calculate(), Ok(Value::Integer(503)));
+        assert_eq!("0XfF".calculate(), Ok(Value::Integer(255)));
+        assert_eq!("-0x10".calculate(), Ok(Value::Integer(-16)));
+        assert_eq!("1_000_000 * 2".calculate(), Ok(Value::Integer(2_000_000)));
+        assert_eq!("1_000.25".calculate(), Ok(Value::Float(1000.25)));
+        assert_eq!(
+            "0xFFFF_FFFF_FFFF_FFFF".calculate().unwrap().to_string(),
+            u64::MAX.to_string()
+        );
+        let err = "0b102".calculate().unwrap_err();
+        assert_eq!(err.code(), "E0007");
+        assert_eq!(err.span(), Some(Span::new(4, 5)));
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!("0xF0 | 0x0F".calculate(), Ok(Value::Integer(0xFF)));
+        assert_eq!("0b1100 & 0b1010".calculate(), Ok(Value::Integer(0b1000)));
+        assert_eq!("0b1100 xor 0b1010".calculate(), Ok(Value::Integer(0b0110)));
+        assert_eq!("~0".calculate(), Ok(Value::Integer(-1)));
+        assert_eq!("~0xFF & 0xFFF".calculate(), Ok(Value::Integer(0xF00)));
+        assert_eq!("1 << 4 + 1".calculate(), Ok(Value::Integer(32)));
+        assert_eq!("-16 >> 2".calculate(), Ok(Value::Integer(-4)));
+        assert_eq!("1 >> 100".calculate(), Ok(Value::Integer(0)));
+        assert_eq!("1 | 2 xor 3 & 6".calculate(), Ok(Value::Integer(1)));
+        assert_eq!("0b101 & 4 = 4".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!(
+            "(1 < 2) & (2 < 1) | (1 < 2)".calculate(),
+            Ok(Value::Boolean(true))
+        );
+        assert_eq!(
+            "1 << 64".calculate().unwrap().to_string(),
+            "18446744073709551616"
+        );
+        assert_eq!(
+            "0xFFFF_FFFF_FFFF_FFFF & ~0xFF"
+                .calculate()
+                .unwrap()
+                .to_string(),
+            "18446744073709551360"
+        );
+        assert_eq!("(1 << 70) >> 68".calculate(), Ok(Value::Integer(4)));
+        assert_eq!("1.5 & 1".calculate().unwrap_err().code(), "E0104");
+        assert_eq!("~1.5".calculate().unwrap_err().code(), "E0105");
+        assert_eq!("1 << -1".calculate().unwrap_err().code(), "E0104");
+    }
+
+    #[test]
+    fn test_trailing_input() {
+        assert_eq!(
+            "1 2".calculate(),
+            Err(Error::Parse(
+                ParseError::UnexpectedToken("2".into()),
+                Span::new(2, 3)
+            ))
+        );
+        assert_eq!(
+            "(1+1))".calculate(),
+            Err(Error::Parse(
+                ParseError::UnexpectedToken(")".into()),
+                Span::new(5, 6)
+            ))
+        );
+        assert!("1 + 1 $".calculate().is_err());
+
+        let calculator = Calculator::new();
+        let context = Context::new();
+        assert_eq!(
+            calculator.calculate_prefix("(1+1)) rest", &context),
+            Ok((Value::Integer(2), 5))
+        );
+        assert_eq!(
+            calculator.calculate_prefix("1 + 2 $", &context),
+            Ok((Value::Integer(3), 6))
+        );
+        assert_eq!(
+            calculator.calculate_prefix("1 + 2  ", &context),
+            Ok((Value::Integer(3), 7))
+        );
+        assert!(calculator.calculate_prefix("1 +", &context).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!("1 = 1".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("1 <> 1".calculate(), Ok(Value::Boolean(false)));
+        assert_eq!("1 != 2".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("3!=6".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("3! = 6".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("2 > 1.5".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("2 >= 2".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("2 < 2".calculate(), Ok(Value::Boolean(false)));
+        assert_eq!("2.0 <= 2".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("20 * 5 >= 100".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("1 + 1 = 4 / 2".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("(1 < 2) = (2 < 3)".calculate(), Ok(Value::Boolean(true)));
+        assert!("(1 < 2) + 1".calculate().is_err());
+        assert!("(1 < 2) > 1".calculate().is_err());
+    }
+
+    #[test]
+    fn test_logic() {
+        assert_eq!("true and false".calculate(), Ok(Value::Boolean(false)));
+        assert_eq!("true && true".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("false or true".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("false || false".calculate(), Ok(Value::Boolean(false)));
+        assert_eq!("not false".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!("not 1 = 2".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!(
+            "true or true and false".calculate(),
+            Ok(Value::Boolean(true))
+        );
+        assert_eq!("not true or true".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!(
+            "1 < 2 and 2 < 3 = true".calculate(),
+            Ok(Value::Boolean(true))
+        );
+        let context = Context::new().with("age", 20).with("country", 1);
+        assert_eq!(
+            "age >= 18 and country = 1".calculate_with(&context),
+            Ok(Value::Boolean(true))
+        );
+        // The right operand isn't evaluated when the left one decides
+        assert_eq!("false and 1 / 0".calculate(), Ok(Value::Boolean(false)));
+        assert_eq!("true or unknown".calculate(), Ok(Value::Boolean(true)));
+        assert_eq!(
+            "true and 1".calculate(),
+            Err(Error::Value(
+                ValueError::InvalidOperand {
+                    operation: "apply and to",
+                    value: Value::Integer(1),
+                },
+                Some(Span::new(9, 10))
+            ))
+        );
+        assert_eq!("not 0".calculate().unwrap_err().code(), "E0105");
+        assert_eq!("true + 1".calculate().unwrap_err().code(), "E0104");
+        assert_eq!("true * false".calculate().unwrap_err().code(), "E0104");
+        assert_eq!("-true".calculate().unwrap_err().code(), "E0105");
+        assert_eq!("true!".calculate().unwrap_err().code(), "E0105");
+        assert!("1 + not true".calculate().is_err());
+    }
+
+    #[test]
+    fn test_conditional() {
+        let context = Context::new().with("x", 0).with("y", 4);
+        let calculate = |input: &str| input.calculate_with(&context);
+        assert_eq!(calculate("x = 0 ? 0 : 1/x"), Ok(Value::Integer(0)));
+        assert_eq!(calculate("y = 0 ? 0 : 1/y"), "1/4".calculate());
+        assert_eq!(calculate("y > 2 ? y * 2 : y"), Ok(Value::Integer(8)));
+        assert_eq!(
+            calculate("x > 0 ? 1 : x < 0 ? -1 : 0"),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(calculate("x = 0 or y = 0 ? 1 : 2"), Ok(Value::Integer(1)));
+        assert_eq!(calculate("1 + (true ? 1 : 2) * 3"), Ok(Value::Integer(4)));
+        assert_eq!(calculate("true ? false ? 1 : 2 : 3"), Ok(Value::Integer(2)));
+        assert_eq!(
+            calculate("y ? 1 : 2"),
+            Err(Error::Value(
+                ValueError::InvalidOperand {
+                    operation: "branch on",
+                    value: Value::Integer(4),
+                },
+                Some(Span::new(0, 1))
+            ))
+        );
+        assert_eq!(
+            calculate("true ? 1"),
+            Err(Error::Parse(ParseError::UnexpectedEnd, Span::new(8, 8)))
+        );
+        assert_eq!(calculate("true ? 1 , 2").unwrap_err().code(), "E0004");
+    }
+
+    #[test]
+    fn test_parse() {
+        let expression = parse("1 + x").unwrap();
+        assert_eq!(
+            expression,
+            Expression::new(
+                Operation::Add(
+                    Box::new(Expression::new(Literal::Integer(1), Span::new(0, 1))),
+                    Box::new(Expression::new(
+                        ExpressionKind::Variable("x".into()),
+                        Span::new(4, 5)
+                    )),
+                ),
+                Span::new(0, 5)
+            )
+        );
+        assert_eq!(parse("1 +").unwrap_err().code(), "E0003");
+        assert_eq!(parse(&"(".repeat(1000)).unwrap_err().code(), "E0201");
+        // Expressions can be taken apart by value
+        let Expression { kind, span } = expression;
+        assert_eq!(span, Span::new(0, 5));
+        match kind {
+            ExpressionKind::Operation(Operation::Add(lhs, _)) => {
+                assert_eq!(lhs.kind, ExpressionKind::Literal(Literal::Integer(1)))
+            }
+            kind => panic!("{:?}", kind),
+        }
+
+        let calculator = Calculator::builder().decimal_literals(true).build();
+        let expression = calculator.parse("0.5").unwrap();
+        assert!(matches!(
+            expression.kind,
+            ExpressionKind::Literal(Literal::Decimal(_))
+        ));
+
+        // Transformed expressions are evaluated as if they had been parsed
+        let mut expression = parse("1 + x").unwrap();
+        if let ExpressionKind::Operation(Operation::Add(_, rhs)) = &mut expression.kind {
+            rhs.kind = ExpressionKind::Literal(Literal::Integer(41));
+        }
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let calculator = Calculator::builder().backend(backend).build();
+            assert_eq!(
+                calculator.evaluate(&expression, &Context::new()),
+                Ok(Value::Integer(42))
+            );
+        }
+
+        let compiled = CompiledExpression::new("2 * x").unwrap();
+        let cached = compiled.clone();
+        drop(compiled);
+        assert_eq!(
+            cached.eval(&Context::new().with("x", 21)),
+            Ok(Value::Integer(42))
+        );
+    }
+
+    #[test]
+    fn test_compiled_expression() {
+        let expression = CompiledExpression::new("x > 0 ? 1 / x : 0").unwrap();
+        assert_eq!(
+            expression.eval(&Context::new().with("x", 4)),
+            "1/4".calculate()
+        );
+        assert_eq!(
+            expression.eval(&Context::new().with("x", 0)),
+            Ok(Value::Integer(0))
+        );
+        assert_eq!(
+            expression.eval(&Context::new()),
+            Err(Error::Value(
+                ValueError::UnknownVariable("x".into()),
+                Some(Span::new(0, 1))
+            ))
+        );
+        assert!(CompiledExpression::new("1 +").is_err());
+
+        let calculator = Calculator::builder()
+            .function("double", 1, |args| match args {
+                [Value::Integer(i)] => Ok(Value::Integer(i * 2)),
+                _ => Err(Error::custom("double expects an integer")),
+            })
+            .decimal_literals(true)
+            .build();
+        let expression = calculator.compile("double(n) * 0.5").unwrap();
+        for n in 0..10 {
+            assert_eq!(
+                expression.eval(&Context::new().with("n", n)),
+                calculator.calculate_with("double(n) * 0.5", &Context::new().with("n", n))
+            );
+        }
+        assert_eq!(
+            expression
+                .eval(&Context::new().with("n", 3))
+                .unwrap()
+                .to_string(),
+            "3.0"
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let code = |input: &str| input.calculate().unwrap_err().code();
+        assert_eq!(code(&"1".repeat(100_000)), "E0200");
+        let nested = format!("{}1{}", "(".repeat(30_000), ")".repeat(30_000));
+        assert_eq!(code(&nested), "E0201");
+        assert_eq!(code(&format!("{}1", "-".repeat(30_000))), "E0201");
+        assert_eq!(code(&format!("max({}1)", "1, ".repeat(20_000))), "E0202");
+        assert_eq!(code("9999999!"), "E0203");
+        assert_eq!(code("3^1000000"), "E0204");
+        assert_eq!(code("1 << 4000000000"), "E0204");
+        assert_eq!(code("2^(2^64)"), "E0103");
+        assert_eq!(
+            "1 + 1e99999999d".calculate(),
+            Err(Error::Limit(
+                LimitError::ExponentTooLarge { max: 100_000 },
+                Some(Span::new(4, 15))
+            ))
+        );
+        assert_eq!(code("1000!^10000"), "E0205");
+        // Multiplying big integers and normalising large rationals take steps
+        // quadratic in their size, so they run out of fuel before doing the
+        // work
+        let powers = ["99999^99999"; 10].join(" + ");
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let calculator = Calculator::builder().backend(backend).build();
+            for input in [
+                "1000!^1000",
+                "99999^99999",
+                &powers,
+                "(7^99999*7^99999)",
+                "3^40000 * 3^40000",
+                "(2/3)^9999",
+                "(2/3)^30000",
+                "(3^9999 + 1) / (2^9999 + 1)",
+            ] {
+                let start = std::time::Instant::now();
+                assert_eq!(calculator.calculate(input).unwrap_err().code(), "E0205");
+                assert!(
+                    start.elapsed() < std::time::Duration::from_secs(1),
+                    "{}",
+                    input
+                );
+            }
+        }
+        // Nesting up to the limit is evaluated without overflowing the stack
+        let nested = format!("{}1{}", "-(".repeat(63), ")".repeat(63));
+        assert_eq!(nested.calculate(), Ok(Value::Integer(-1)));
+        let nested = format!("{}1", "-".repeat(127));
+        assert_eq!(nested.calculate(), Ok(Value::Integer(-1)));
+        assert_eq!("1000!".calculate().map(|_| ()), Ok(()));
+
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let calculator = Calculator::builder()
+                .backend(backend)
+                .limits(EvalLimits::new().fuel(5).max_factorial(10))
+                .build();
+            assert_eq!(calculator.calculate("1 + 2"), Ok(Value::Integer(3)));
+            let err = calculator.calculate("1 + 2 + 3 + 4 + 5 + 6").unwrap_err();
+            assert!(matches!(
+                err,
+                Error::Limit(LimitError::OutOfFuel { fuel: 5 }, Some(_))
+            ));
+            assert_eq!(calculator.calculate("11!").unwrap_err().code(), "E0203");
+        }
+        let calculator = Calculator::builder()
+            .limits(EvalLimits::unlimited())
+            .build();
+        assert_eq!(
+            calculator.calculate("1 << 200000 > 0"),
+            Ok(Value::Boolean(true))
+        );
+    }
+
+    #[test]
+    fn test_calculate_with() {
+        let context = Context::new()
+            .with("qty", 4)
+            .with("unit_price", 2.5)
+            .with("member", true);
+        assert_eq!(
+            "qty * unit_price".calculate_with(&context),
+            Ok(Value::Float(10.0))
+        );
+        assert_eq!(
+            "qty * unit_price >= 10".calculate_with(&context),
+            Ok(Value::Boolean(true))
+        );
+        assert_eq!("member".calculate_with(&context), Ok(Value::Boolean(true)));
+        assert_eq!(
+            "qty * discount".calculate_with(&context),
+            Err(Error::Value(
+                ValueError::UnknownVariable("discount".into()),
+                Some(Span::new(6, 14))
+            ))
+        );
+        assert!("qty".calculate().is_err());
+    }
+
+    #[test]
+    fn test_call() {
+        assert_eq!("sqrt(16)".calculate(), Ok(Value::Float(4.0)));
+        assert_eq!("abs(-3)".calculate(), Ok(Value::Integer(3)));
+        assert_eq!("abs(-3.5)".calculate(), Ok(Value::Float(3.5)));
+        assert_eq!("floor(2.7) + ceil(2.2)".calculate(), Ok(Value::Float(5.0)));
+        assert_eq!("round(2.5)".calculate(), Ok(Value::Float(3.0)));
+        assert_eq!("round(2.71828, 2)".calculate(), Ok(Value::Float(2.72)));
+        assert_eq!("round(5, 2)".calculate(), Ok(Value::Integer(5)));
+        assert_eq!("round(1250, -2)".calculate(), Ok(Value::Integer(1300)));
+        assert_eq!("round(-1249, -2)".calculate(), Ok(Value::Integer(-1200)));
+        assert_eq!(
+            "round(2^70 + 1, -3)".calculate().unwrap().to_string(),
+            "1180591620717411303000"
+        );
+        assert_eq!("round(1.5, 400)".calculate(), Ok(Value::Float(1.5)));
+        assert_eq!("round(1234.5, -2)".calculate(), Ok(Value::Float(1200.0)));
+        assert_eq!("round(1e300, -400)".calculate(), Ok(Value::Float(0.0)));
+        assert_eq!("round(1.5, 1001)".calculate().unwrap_err().code(), "E0109");
+        assert_eq!("round(1.5, 0.5)".calculate().unwrap_err().code(), "E0109");
+        assert_eq!("max(1, 2i)".calculate().unwrap_err().code(), "E0109");
+        assert_eq!("min(1, true)".calculate().unwrap_err().code(), "E0109");
+        assert_eq!("min(3, 1.5, 2)".calculate(), Ok(Value::Float(1.5)));
+        assert_eq!("max(3, 1.5, 2) * 2".calculate(), Ok(Value::Integer(6)));
+        assert_eq!("ln(exp(2))".calculate(), Ok(Value::Float(2.0)));
+        assert_eq!("log(1000)".calculate(), Ok(Value::Float(3.0)));
+        assert_eq!("log(8, 2)".calculate(), Ok(Value::Float(3.0)));
+        assert_eq!(
+            "sin(0) + cos(0) + tan(0)".calculate(),
+            Ok(Value::Float(1.0))
+        );
+        assert_eq!(
+            "sqrt(1, 2)".calculate(),
+            Err(Error::Value(
+                ValueError::ArityMismatch {
+                    function: "sqrt".into(),
+                    expected: Arity::Exact(1),
+                    found: 2,
+                },
+                Some(Span::new(0, 10))
+            ))
+        );
+        assert_eq!(
+            "max()".calculate(),
+            Err(Error::Value(
+                ValueError::ArityMismatch {
+                    function: "max".into(),
+                    expected: Arity::AtLeast(1),
+                    found: 0,
+                },
+                Some(Span::new(0, 5))
+            ))
+        );
+        assert_eq!(
+            "sqrt(1 < 2)".calculate(),
+            Err(Error::Value(
+                ValueError::InvalidArgument {
+                    function: "sqrt".into(),
+                    expected: "a number",
+                    found: Value::Boolean(true),
+                },
+                Some(Span::new(0, 11))
+            ))
+        );
+        assert_eq!(
+            "foo(1)".calculate(),
+            Err(Error::Value(
+                ValueError::UnknownFunction("foo".into()),
+                Some(Span::new(0, 6))
+            ))
+        );
+        assert!("sqrt(1,)".calculate().is_err());
+        assert!("sqrt(1".calculate().is_err());
+    }
+
+    #[test]
+    fn test_error_span() {
+        assert_eq!(
+            "1 + 2 * (3 / (4 - 4))".calculate(),
+            Err(Error::Value(
+                ValueError::DivisionByZero,
+                Some(Span::new(8, 21))
+            ))
+        );
+        assert_eq!(
+            "2 * -(1 < 2) + 1".calculate(),
+            Err(Error::Value(
+                ValueError::InvalidOperand {
+                    operation: "negate",
+                    value: Value::Boolean(true),
+                },
+                Some(Span::new(4, 12))
+            ))
+        );
+        assert_eq!(
+            "1 + 2 *".calculate(),
+            Err(Error::Parse(ParseError::UnexpectedEnd, Span::new(7, 7)))
+        );
+        assert_eq!(
+            "(1 + 2 3".calculate(),
+            Err(Error::Parse(
+                ParseError::ExpectedToken {
+                    expected: ")".into(),
+                    found: "3".into(),
+                },
+                Span::new(7, 8)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_native_function() {
+        let calculator = Calculator::builder()
+            .function("tax", 1, |args| match args {
+                [Value::Integer(income)] if *income > 1000 => Ok(Value::Integer(income / 5)),
+                [Value::Integer(income)] => Ok(Value::Integer(income / 10)),
+                _ => Err(Error::custom("tax expects an integer")),
+            })
+            .function("sum", Arity::AtLeast(0), |args| {
+                Ok(Value::Integer(
+                    args.iter()
+                        .map(|arg| match arg {
+                            Value::Integer(i) => *i,
+                            _ => 0,
+                        })
+                        .sum(),
+                ))
+            })
+            .function("sqrt", 1, |_| Ok(Value::Integer(0)))
+            .build();
+        let context = Context::new().with("income", 2000);
+        assert_eq!(
+            calculator.calculate_with("tax(income) + 1", &context),
+            Ok(Value::Integer(401))
+        );
+        assert_eq!(calculator.calculate("tax(500)"), Ok(Value::Integer(50)));
+        assert_eq!(calculator.calculate("sum()"), Ok(Value::Integer(0)));
+        assert_eq!(calculator.calculate("sum(1, 2, 3)"), Ok(Value::Integer(6)));
+        assert_eq!(calculator.calculate("sqrt(4)"), Ok(Value::Integer(0)));
+        assert_eq!(calculator.calculate("abs(-4)"), Ok(Value::Integer(4)));
+        assert_eq!(
+            calculator.calculate("tax(1.5)"),
+            Err(Error::Value(
+                ValueError::Custom("tax expects an integer".into()),
+                Some(Span::new(0, 8))
+            ))
+        );
+        assert_eq!(
+            calculator.calculate("tax(1, 2)"),
+            Err(Error::Value(
+                ValueError::ArityMismatch {
+                    function: "tax".into(),
+                    expected: Arity::Exact(1),
+                    found: 2,
+                },
+                Some(Span::new(0, 9))
+            ))
+        );
+        assert!(Calculator::new().calculate("tax(1)").is_err());
+    }
 }
@@ -0,0 +1,158 @@
+use crate::ast::Expression;
+use crate::error::{Error, LimitError, Result};
+
+/// Limits on the resources an expression may use, to safely evaluate input
+/// from untrusted users. Exceeding a limit fails with an [`Error::Limit`].
+///
+/// ```
+/// use calculator_rs::{Calculator, EvalLimits};
+///
+/// let calculator = Calculator::builder()
+///     .limits(EvalLimits::new().max_factorial(100))
+///     .build();
+/// assert_eq!(calculator.calculate("9999999!").unwrap_err().code(), "E0203");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvalLimits {
+    pub(crate) max_input_length: usize,
+    pub(crate) max_depth: usize,
+    pub(crate) max_nodes: usize,
+    pub(crate) max_factorial: u64,
+    pub(crate) max_exponent: u64,
+    pub(crate) fuel: u64,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        EvalLimits {
+            max_input_length: 64 * 1024,
+            max_depth: 128,
+            max_nodes: 10_000,
+            max_factorial: 1000,
+            max_exponent: 100_000,
+            fuel: 1_000_000,
+        }
+    }
+}
+
+impl EvalLimits {
+    pub fn new() -> EvalLimits {
+        EvalLimits::default()
+    }
+
+    /// Returns limits that are never exceeded, for trusted input. Deeply
+    /// nested expressions may then overflow the stack unless they are
+    /// evaluated with [`Backend::Bytecode`].
+    ///
+    /// [`Backend::Bytecode`]: crate::Backend::Bytecode
+    pub fn unlimited() -> EvalLimits {
+        EvalLimits {
+            max_input_length: usize::MAX,
+            max_depth: usize::MAX,
+            max_nodes: usize::MAX,
+            max_factorial: u64::MAX,
+            max_exponent: u64::MAX,
+            fuel: u64::MAX,
+        }
+    }
+
+    /// Sets the length of the input in bytes. Defaults to 64 KiB.
+    pub fn max_input_length(mut self, length: usize) -> EvalLimits {
+        self.max_input_length = length;
+        self
+    }
+
+    /// Sets how deeply expressions and parentheses may be nested. Defaults
+    /// to 128, which the tree-walking backend evaluates within a 2 MiB stack.
+    pub fn max_depth(mut self, depth: usize) -> EvalLimits {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Sets the number of literals, variables, calls and operations in an
+    /// expression. Defaults to 10,000.
+    pub fn max_nodes(mut self, nodes: usize) -> EvalLimits {
+        self.max_nodes = nodes;
+        self
+    }
+
+    /// Sets the largest number that the factorial can be taken of. Defaults
+    /// to 1000.
+    pub fn max_factorial(mut self, n: u64) -> EvalLimits {
+        self.max_factorial = n;
+        self
+    }
+
+    /// Sets the largest exponent of an exact power (of integers, rationals or
+    /// decimals), amount of a left shift, or exponent of a decimal literal.
+    /// Defaults to 100,000.
+    pub fn max_exponent(mut self, exponent: u64) -> EvalLimits {
+        self.max_exponent = exponent;
+        self
+    }
+
+    /// Sets the number of steps evaluation may take. Each literal, variable,
+    /// call and operation evaluated takes a step with either backend. Adding,
+    /// subtracting, shifting and bitwise operations on big integers take a
+    /// step for every 64 bits of their operands, while multiplying and
+    /// dividing them, powers, factorials and arithmetic on rationals, which
+    /// must be normalised, take steps in proportion to the product of the
+    /// sizes of the numbers multiplied. Defaults to 1,000,000.
+    pub fn fuel(mut self, fuel: u64) -> EvalLimits {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Checks the depth and number of nodes of a parsed expression, without
+    /// recursing so deeply nested expressions can be checked
+    pub(crate) fn check(&self, expression: &Expression) -> Result<()> {
+        let mut nodes = 0;
+        let mut stack = vec![(expression, 1)];
+        while let Some((expression, depth)) = stack.pop() {
+            nodes += 1;
+            if nodes > self.max_nodes {
+                let max = self.max_nodes;
+                return Err(Error::Limit(
+                    LimitError::TooManyNodes { max },
+                    Some(expression.span),
+                ));
+            }
+            if depth > self.max_depth {
+                let max = self.max_depth;
+                return Err(Error::Limit(
+                    LimitError::NestingTooDeep { max },
+                    Some(expression.span),
+                ));
+            }
+            let operands = expression.kind.operands();
+            stack.extend(operands.into_iter().rev().map(|e| (e, depth + 1)));
+        }
+        Ok(())
+    }
+}
+
+/// The steps remaining while evaluating an expression
+pub(crate) struct Fuel {
+    remaining: u64,
+    budget: u64,
+}
+
+impl Fuel {
+    pub(crate) fn new(limits: &EvalLimits) -> Fuel {
+        Fuel {
+            remaining: limits.fuel,
+            budget: limits.fuel,
+        }
+    }
+
+    /// Takes the given number of steps, failing if there aren't enough left
+    pub(crate) fn burn(&mut self, steps: u64) -> Result<()> {
+        match self.remaining.checked_sub(steps) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(())
+            }
+            None => Err(LimitError::OutOfFuel { fuel: self.budget }.into()),
+        }
+    }
+}
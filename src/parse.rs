@@ -1,110 +1,365 @@
 use crate::ast;
+use crate::bigint::BigInt;
 use crate::lexer::{Lexer, Token};
+use crate::limits::EvalLimits;
+use crate::span::Span;
 
-use crate::error::{Error, Result};
+use crate::error::{Error, LimitError, ParseError, Result};
 
 pub(crate) struct Parser<'a> {
     lexer: std::iter::Peekable<Lexer<'a>>,
+    /// The span of the end of input, used to locate errors there
+    eof: Span,
+    /// Whether fractional number literals are parsed as decimals
+    decimals: bool,
+    limits: EvalLimits,
 }
 
 impl Parser<'_> {
-    pub fn new(query: &str) -> Parser {
+    pub fn new(query: &str) -> Parser<'_> {
         Parser {
             lexer: Lexer::new(query).peekable(),
+            eof: Span::new(query.len(), query.len()),
+            decimals: false,
+            limits: EvalLimits::unlimited(),
         }
     }
 
+    /// Parses number literals with a fractional part as decimals
+    pub fn decimal_literals(mut self, enabled: bool) -> Self {
+        self.decimals = enabled;
+        self
+    }
+
+    /// Limits the length of the input, and the depth and number of nodes of
+    /// the expression
+    pub fn limits(mut self, limits: EvalLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Parses the input as a single expression, failing if anything follows it
     pub fn parse(&mut self) -> Result<ast::Expression> {
-        self.parse_expression(0)
+        self.check_length()?;
+        let expr = self.parse_expression()?;
+        self.next_expect(None)?;
+        self.limits.check(&expr.0)?;
+        Ok(expr.into_inner())
     }
 
-    fn parse_expression(&mut self, min_prec: u8) -> Result<ast::Expression> {
-        let mut lhs = if let Some(prefix) = self.next_if_operator::<PrefixOperator>(min_prec)? {
-            prefix.build(self.parse_expression(prefix.prec() + prefix.assoc())?)
-        } else {
-            self.parse_expression_atom()?
+    /// Parses an expression from the start of the input, returning it along
+    /// with the byte offset of the first token following it (or the input
+    /// length if there is none).
+    pub fn parse_prefix(&mut self) -> Result<(ast::Expression, usize)> {
+        self.check_length()?;
+        let expr = self.parse_expression()?;
+        self.limits.check(&expr.0)?;
+        let offset = match self.lexer.peek() {
+            Some(Ok((_, span))) => span.start,
+            Some(Err(err)) => err.span().unwrap_or(self.eof).start,
+            None => self.eof.start,
         };
-        while let Some(postfix) = self.next_if_operator::<PostfixOperator>(min_prec)? {
-            lhs = postfix.build(lhs)
-        }
-        while let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec)? {
-            lhs = infix.build(lhs, self.parse_expression(infix.prec() + infix.assoc())?)
+        Ok((expr.into_inner(), offset))
+    }
+
+    fn check_length(&self) -> Result<()> {
+        if self.eof.start > self.limits.max_input_length {
+            let max = self.limits.max_input_length;
+            return Err(Error::Limit(LimitError::InputTooLong { max }, None));
         }
-        Ok(lhs)
+        Ok(())
     }
 
-    fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
-        Ok(match self.next()? {
-            Token::Number(n) => {
-                if n.chars().all(|c| c.is_ascii_digit()) {
-                    ast::Literal::Integer(n.parse()?).into()
+    /// Parses an expression, keeping the expressions that operands are nested
+    /// in on a stack rather than recursing, and failing before they are
+    /// nested too deeply
+    fn parse_expression(&mut self) -> Result<Partial> {
+        // The frames waiting for an operand, each with the precedence of the
+        // expression it is part of
+        let mut frames: Vec<(Frame, u8)> = Vec::new();
+        let mut min_prec = 0;
+        'operand: loop {
+            if frames.len() >= self.limits.max_depth {
+                let span = match self.lexer.peek() {
+                    Some(Ok((_, span))) => *span,
+                    _ => self.eof,
+                };
+                let max = self.limits.max_depth;
+                return Err(Error::Limit(LimitError::NestingTooDeep { max }, Some(span)));
+            }
+            let mut lhs =
+                if let Some((prefix, span)) = self.next_if_operator::<PrefixOperator>(min_prec)? {
+                    let prec = prefix.prec() + prefix.assoc();
+                    frames.push((Frame::Prefix(prefix, span), min_prec));
+                    min_prec = prec;
+                    continue;
                 } else {
-                    ast::Literal::Float(n.parse()?).into()
+                    match self.parse_expression_atom()? {
+                        Atom::Expression(expr) => Partial(expr),
+                        Atom::Open(frame) => {
+                            frames.push((frame, min_prec));
+                            min_prec = 0;
+                            continue;
+                        }
+                    }
+                };
+            let mut postfix = true;
+            loop {
+                // Postfix operators only follow the start of an operand
+                if postfix {
+                    while let Some((op, span)) =
+                        self.next_if_operator::<PostfixOperator>(min_prec)?
+                    {
+                        lhs = Partial(op.build(span, lhs.into_inner()))
+                    }
+                }
+                if let Some((infix, _)) = self.next_if_operator::<InfixOperator>(min_prec)? {
+                    let (frame, prec) = match infix {
+                        // The expression between `?` and `:` may be any
+                        // expression as it is delimited
+                        InfixOperator::Conditional(_) => (Frame::Conditional(lhs), 0),
+                        infix => {
+                            let prec = infix.prec() + infix.assoc();
+                            (Frame::Infix(infix, lhs), prec)
+                        }
+                    };
+                    frames.push((frame, min_prec));
+                    min_prec = prec;
+                    continue 'operand;
                 }
+                // The operand is complete, so it completes the frame waiting
+                // for it
+                let (frame, prec) = match frames.pop() {
+                    Some(frame) => frame,
+                    None => return Ok(lhs),
+                };
+                min_prec = prec;
+                postfix = true;
+                lhs = match frame {
+                    Frame::Prefix(prefix, span) => Partial(prefix.build(span, lhs.into_inner())),
+                    Frame::Infix(infix, left) => {
+                        postfix = false;
+                        Partial(infix.build(left.into_inner(), lhs.into_inner()))
+                    }
+                    Frame::Conditional(condition) => {
+                        self.next_expect(Some(Token::Colon))?;
+                        let infix = InfixOperator::Conditional(Some(lhs));
+                        let prec = infix.prec() + infix.assoc();
+                        frames.push((Frame::Infix(infix, condition), min_prec));
+                        min_prec = prec;
+                        continue 'operand;
+                    }
+                    Frame::Parenthesis(open) => {
+                        if let Some((_, close)) = self.next_expect(Some(Token::CloseParen))? {
+                            lhs.0.span = open.join(close);
+                        }
+                        lhs
+                    }
+                    Frame::Call(name, span, mut args) => {
+                        args.push(lhs);
+                        if self.next_if_token(Token::Comma)?.is_some() {
+                            frames.push((Frame::Call(name, span, args), min_prec));
+                            min_prec = 0;
+                            continue 'operand;
+                        }
+                        let close = self
+                            .next_expect(Some(Token::CloseParen))?
+                            .map_or(self.eof, |(_, close)| close);
+                        let args = args.into_iter().map(Partial::into_inner).collect();
+                        Partial(ast::Expression::new(
+                            ast::ExpressionKind::Call(name, args),
+                            span.join(close),
+                        ))
+                    }
+                };
             }
-            Token::OpenParen => {
-                let expr = self.parse_expression(0)?;
-                self.next_expect(Some(Token::CloseParen))?;
-                expr
+        }
+    }
+
+    fn parse_expression_atom(&mut self) -> Result<Atom> {
+        let (token, span) = self.next()?;
+        Ok(Atom::Expression(match token {
+            Token::Number(number) => {
+                let invalid = || Error::Parse(ParseError::InvalidNumber(number.clone()), span);
+                let n = number.replace('_', "");
+                let radix = match n.get(..2) {
+                    Some("0x" | "0X") => 16,
+                    Some("0o" | "0O") => 8,
+                    Some("0b" | "0B") => 2,
+                    _ => 10,
+                };
+                // The lexer has validated the number, so it is an integer if it
+                // has neither a fraction nor an exponent
+                let literal = if radix != 10 {
+                    match i64::from_str_radix(&n[2..], radix) {
+                        Ok(i) => ast::Literal::Integer(i),
+                        Err(_) => ast::Literal::BigInteger(
+                            BigInt::from_str_radix(&n[2..], radix).ok_or_else(invalid)?,
+                        ),
+                    }
+                } else if let Some(digits) = n.strip_suffix('d') {
+                    self.check_exponent(digits, span)?;
+                    ast::Literal::Decimal(digits.parse().map_err(|_| invalid())?)
+                } else if let Some(digits) = n.strip_suffix('i') {
+                    ast::Literal::Imaginary(parse_float(digits).ok_or_else(invalid)?)
+                } else if n.chars().all(|c| c.is_ascii_digit()) {
+                    match n.parse() {
+                        Ok(i) => ast::Literal::Integer(i),
+                        Err(_) => ast::Literal::BigInteger(n.parse().map_err(|_| invalid())?),
+                    }
+                } else if self.decimals {
+                    self.check_exponent(&n, span)?;
+                    ast::Literal::Decimal(n.parse().map_err(|_| invalid())?)
+                } else {
+                    ast::Literal::Float(parse_float(&n).ok_or_else(invalid)?)
+                };
+                ast::Expression::new(literal, span)
+            }
+            Token::Ident(name) => {
+                if self.next_if_token(Token::OpenParen)?.is_none() {
+                    ast::Expression::new(ast::ExpressionKind::Variable(name), span)
+                } else if let Some((_, close)) = self.next_if_token(Token::CloseParen)? {
+                    let call = ast::ExpressionKind::Call(name, Vec::new());
+                    ast::Expression::new(call, span.join(close))
+                } else {
+                    return Ok(Atom::Open(Frame::Call(name, span, Vec::new())));
+                }
             }
+            Token::True => ast::Expression::new(ast::Literal::Boolean(true), span),
+            Token::False => ast::Expression::new(ast::Literal::Boolean(false), span),
+            Token::OpenParen => return Ok(Atom::Open(Frame::Parenthesis(span))),
             t => {
-                return Err(Error::Parse(format!(
-                    "Expected expression atom, found {}",
-                    t
-                )));
+                return Err(Error::Parse(
+                    ParseError::ExpectedExpression(t.to_string()),
+                    span,
+                ));
             }
-        })
+        }))
     }
 
-    fn next(&mut self) -> Result<Token> {
+    /// Checks the exponent of a decimal literal, which is expanded into its
+    /// digits
+    fn check_exponent(&self, number: &str, span: Span) -> Result<()> {
+        let exponent = match number.split_once(['e', 'E']) {
+            Some((_, exponent)) => exponent,
+            None => return Ok(()),
+        };
+        let exponent = exponent.parse::<i64>().map_or(u64::MAX, i64::unsigned_abs);
+        if exponent > self.limits.max_exponent {
+            let max = self.limits.max_exponent;
+            return Err(Error::Limit(
+                LimitError::ExponentTooLarge { max },
+                Some(span),
+            ));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<(Token, Span)> {
         self.lexer
             .next()
-            .unwrap_or(Err(Error::Parse("Unexpected end of input".into())))
+            .unwrap_or(Err(Error::Parse(ParseError::UnexpectedEnd, self.eof)))
     }
 
     fn peek(&mut self) -> Result<Option<Token>> {
-        self.lexer.peek().cloned().transpose()
+        self.lexer
+            .peek()
+            .cloned()
+            .transpose()
+            .map(|t| t.map(|(token, _)| token))
     }
 
-    fn next_expect(&mut self, expect: Option<Token>) -> Result<Option<Token>> {
+    fn next_expect(&mut self, expect: Option<Token>) -> Result<Option<(Token, Span)>> {
         if let Some(t) = expect {
-            let token = self.next()?;
+            let (token, span) = self.next()?;
             if token == t {
-                Ok(Some(token))
+                Ok(Some((token, span)))
             } else {
-                Err(Error::Parse(format!(
-                    "Expected token {}, found {}",
-                    t, token
-                )))
+                Err(Error::Parse(
+                    ParseError::ExpectedToken {
+                        expected: t.to_string(),
+                        found: token.to_string(),
+                    },
+                    span,
+                ))
             }
-        } else if let Some(token) = self.peek()? {
-            Err(Error::Parse(format!("Unexpected token {}", token)))
+        } else if let Some((token, span)) = self.lexer.peek().cloned().transpose()? {
+            Err(Error::Parse(
+                ParseError::UnexpectedToken(token.to_string()),
+                span,
+            ))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_if_token(&mut self, token: Token) -> Result<Option<(Token, Span)>> {
+        if self.peek()? == Some(token) {
+            self.next().map(Some)
         } else {
             Ok(None)
         }
     }
 
-    fn next_if_operator<O: Operator>(&mut self, min_prec: u8) -> Result<Option<O>> {
+    fn next_if_operator<O: Operator>(&mut self, min_prec: u8) -> Result<Option<(O, Span)>> {
         if let Some(operator) = self
             .peek()
             .unwrap_or(None)
             .and_then(|t| O::from(&t))
             .filter(|o| o.prec() >= min_prec)
         {
-            self.next()?;
-            Ok(Some(operator))
+            let (_, span) = self.next()?;
+            Ok(Some((operator, span)))
         } else {
             Ok(None)
         }
     }
 }
 
+/// A partly parsed expression, waiting for the operand being parsed to
+/// complete it
+enum Frame {
+    /// A prefix operator and its span, applied to the operand
+    Prefix(PrefixOperator, Span),
+    /// An infix operator and its left operand, applied to the right one
+    Infix(InfixOperator, Partial),
+    /// The condition of a conditional, whose `?` has been parsed
+    Conditional(Partial),
+    /// An opening parenthesis and its span
+    Parenthesis(Span),
+    /// A call, its span up to the opening parenthesis and the arguments parsed
+    /// so far
+    Call(String, Span, Vec<Partial>),
+}
+
+/// An expression being parsed, which is dropped from a stack rather than
+/// recursively if parsing fails, as it may be nested too deeply for that
+struct Partial(ast::Expression);
+
+impl Partial {
+    fn into_inner(mut self) -> ast::Expression {
+        let placeholder = ast::Expression::new(ast::Literal::Integer(0), self.0.span);
+        std::mem::replace(&mut self.0, placeholder)
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        self.0.drop_operands();
+    }
+}
+
+/// The start of an operand, which either is complete or opens an expression
+/// nested in it
+enum Atom {
+    Expression(ast::Expression),
+    Open(Frame),
+}
+
 /// An operator trait, to help with parsing of operators
 trait Operator: Sized {
     /// Looks up the corresponding operator for a token, if one exists
     fn from(token: &Token) -> Option<Self>;
-    /// Augments an operator by allowing it to parse any modifiers.
-    fn augment(self, parser: &mut Parser) -> Result<Self>;
     /// Returns the operator's associativity
     fn assoc(&self) -> u8;
     /// Returns the operator's precedence
@@ -115,18 +370,23 @@ const ASSOC_LEFT: u8 = 1;
 const ASSOC_RIGHT: u8 = 0;
 
 enum PrefixOperator {
+    BitwiseNot,
     Minus,
+    Not,
     Plus,
 }
 
 impl PrefixOperator {
-    fn build(&self, lhs: ast::Expression) -> ast::Expression {
+    fn build(&self, span: Span, lhs: ast::Expression) -> ast::Expression {
+        let span = span.join(lhs.span);
         let lhs = Box::new(lhs);
-        match self {
+        let operation = match self {
+            PrefixOperator::BitwiseNot => ast::Operation::BitwiseNot(lhs),
             PrefixOperator::Minus => ast::Operation::Negate(lhs),
+            PrefixOperator::Not => ast::Operation::Not(lhs),
             PrefixOperator::Plus => ast::Operation::Assert(lhs),
-        }
-        .into()
+        };
+        ast::Expression::new(operation, span)
     }
 }
 
@@ -135,45 +395,86 @@ impl Operator for PrefixOperator {
         match token {
             Token::Minus => Some(Self::Minus),
             Token::Plus => Some(Self::Plus),
+            Token::Tilde => Some(Self::BitwiseNot),
+            Token::Not => Some(Self::Not),
             _ => None,
         }
     }
 
-    fn augment(self, _parser: &mut Parser) -> Result<Self> {
-        Ok(self)
-    }
-
     fn assoc(&self) -> u8 {
         ASSOC_RIGHT
     }
 
+    /// `not` binds more loosely than comparisons, so `not a = b` negates the
+    /// comparison
     fn prec(&self) -> u8 {
-        9
+        match self {
+            Self::Not => 4,
+            Self::BitwiseNot | Self::Minus | Self::Plus => 15,
+        }
     }
 }
 
 enum InfixOperator {
     Add,
+    And,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    /// The `?` of a conditional, holding the expression between it and the
+    /// `:` once that is parsed
+    Conditional(Option<Partial>),
     Divide,
+    Equal,
     Exponentiate,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
     Multiply,
+    NotEqual,
+    Or,
+    ShiftLeft,
+    ShiftRight,
     Subtract,
     Modulo,
 }
 
 impl InfixOperator {
-    fn build(&self, lhs: ast::Expression, rhs: ast::Expression) -> ast::Expression {
+    fn build(self, lhs: ast::Expression, rhs: ast::Expression) -> ast::Expression {
+        let span = lhs.span.join(rhs.span);
         let lhs = Box::new(lhs);
         let rhs = Box::new(rhs);
-        match self {
+        let operation = match self {
+            InfixOperator::Conditional(then) => {
+                let then = then.expect("conditionals are built once the `:` is parsed");
+                let then = Box::new(then.into_inner());
+                return ast::Expression::new(
+                    ast::ExpressionKind::Conditional(lhs, then, rhs),
+                    span,
+                );
+            }
             InfixOperator::Add => ast::Operation::Add(lhs, rhs),
+            InfixOperator::And => ast::Operation::And(lhs, rhs),
+            InfixOperator::BitwiseAnd => ast::Operation::BitwiseAnd(lhs, rhs),
+            InfixOperator::BitwiseOr => ast::Operation::BitwiseOr(lhs, rhs),
+            InfixOperator::BitwiseXor => ast::Operation::BitwiseXor(lhs, rhs),
             InfixOperator::Divide => ast::Operation::Divide(lhs, rhs),
+            InfixOperator::Equal => ast::Operation::Equal(lhs, rhs),
             InfixOperator::Exponentiate => ast::Operation::Exponentiate(lhs, rhs),
+            InfixOperator::GreaterThan => ast::Operation::GreaterThan(lhs, rhs),
+            InfixOperator::GreaterThanOrEqual => ast::Operation::GreaterThanOrEqual(lhs, rhs),
+            InfixOperator::LessThan => ast::Operation::LessThan(lhs, rhs),
+            InfixOperator::LessThanOrEqual => ast::Operation::LessThanOrEqual(lhs, rhs),
             InfixOperator::Multiply => ast::Operation::Multiply(lhs, rhs),
+            InfixOperator::NotEqual => ast::Operation::NotEqual(lhs, rhs),
+            InfixOperator::Or => ast::Operation::Or(lhs, rhs),
+            InfixOperator::ShiftLeft => ast::Operation::ShiftLeft(lhs, rhs),
+            InfixOperator::ShiftRight => ast::Operation::ShiftRight(lhs, rhs),
             InfixOperator::Subtract => ast::Operation::Subtract(lhs, rhs),
             InfixOperator::Modulo => ast::Operation::Modulo(lhs, rhs),
-        }
-        .into()
+        };
+        ast::Expression::new(operation, span)
     }
 }
 
@@ -181,31 +482,57 @@ impl Operator for InfixOperator {
     fn from(token: &Token) -> Option<Self> {
         match token {
             Token::Plus => Some(Self::Add),
-            Token::Minus => Some(Self::Divide),
+            Token::Minus => Some(Self::Subtract),
+            Token::Equal => Some(Self::Equal),
             Token::Caret => Some(Self::Exponentiate),
+            Token::GreaterThan => Some(Self::GreaterThan),
+            Token::GreaterThanOrEqual => Some(Self::GreaterThanOrEqual),
+            Token::LessThan => Some(Self::LessThan),
+            Token::LessThanOrEqual => Some(Self::LessThanOrEqual),
             Token::Asterisk => Some(Self::Multiply),
-            Token::Slash => Some(Self::Subtract),
+            Token::LessOrGreaterThan | Token::ExclamationEqual => Some(Self::NotEqual),
+            Token::Slash => Some(Self::Divide),
             Token::Percent => Some(Self::Modulo),
+            Token::Ampersand => Some(Self::BitwiseAnd),
+            Token::Pipe => Some(Self::BitwiseOr),
+            Token::Xor => Some(Self::BitwiseXor),
+            Token::DoubleLessThan => Some(Self::ShiftLeft),
+            Token::DoubleGreaterThan => Some(Self::ShiftRight),
+            Token::And | Token::DoubleAmpersand => Some(Self::And),
+            Token::Or | Token::DoublePipe => Some(Self::Or),
+            Token::Question => Some(Self::Conditional(None)),
             _ => None,
         }
     }
 
-    fn augment(self, _parser: &mut Parser) -> Result<Self> {
-        Ok(self)
-    }
-
     fn assoc(&self) -> u8 {
         match self {
-            Self::Exponentiate => ASSOC_RIGHT,
+            Self::Conditional(_) | Self::Exponentiate => ASSOC_RIGHT,
             _ => ASSOC_LEFT,
         }
     }
 
+    /// The bitwise operators are ordered among themselves as in C, but bind
+    /// more tightly than comparisons (as in Rust), so `flags & 4 = 4` compares
+    /// the masked value. The logical operators bind more loosely, with `and`
+    /// before `or`, and conditionals most loosely.
     fn prec(&self) -> u8 {
         match self {
-            Self::Add | Self::Subtract => 5,
-            Self::Multiply | Self::Divide | Self::Modulo => 6,
-            Self::Exponentiate => 7,
+            Self::Conditional(_) => 1,
+            Self::Or => 2,
+            Self::And => 3,
+            Self::Equal | Self::NotEqual => 5,
+            Self::GreaterThan
+            | Self::GreaterThanOrEqual
+            | Self::LessThan
+            | Self::LessThanOrEqual => 6,
+            Self::BitwiseOr => 7,
+            Self::BitwiseXor => 8,
+            Self::BitwiseAnd => 9,
+            Self::ShiftLeft | Self::ShiftRight => 10,
+            Self::Add | Self::Subtract => 11,
+            Self::Multiply | Self::Divide | Self::Modulo => 12,
+            Self::Exponentiate => 13,
         }
     }
 }
@@ -215,12 +542,13 @@ enum PostfixOperator {
 }
 
 impl PostfixOperator {
-    fn build(&self, lhs: ast::Expression) -> ast::Expression {
+    fn build(&self, span: Span, lhs: ast::Expression) -> ast::Expression {
+        let span = lhs.span.join(span);
         let lhs = Box::new(lhs);
-        match self {
+        let operation = match self {
             PostfixOperator::Factorial => ast::Operation::Factorial(lhs),
-        }
-        .into()
+        };
+        ast::Expression::new(operation, span)
     }
 }
 
@@ -232,15 +560,90 @@ impl Operator for PostfixOperator {
         }
     }
 
-    fn augment(self, _parser: &mut Parser) -> Result<Self> {
-        Ok(self)
-    }
-
     fn assoc(&self) -> u8 {
         ASSOC_LEFT
     }
 
     fn prec(&self) -> u8 {
-        8
+        14
+    }
+}
+
+/// How an operation is written, with the precedence and associativity it is
+/// parsed with, so expressions can be printed back as source
+pub(crate) enum Fixity {
+    Prefix(Token, u8),
+    Infix(Token, u8, u8),
+    Postfix(Token, u8),
+}
+
+impl Fixity {
+    /// Returns the fixity of an operation or conditional, written with the
+    /// token of its operator (the `?` for a conditional)
+    pub(crate) fn of(kind: &ast::ExpressionKind) -> Option<Fixity> {
+        use ast::Operation::*;
+        let operation = match kind {
+            ast::ExpressionKind::Operation(operation) => operation,
+            ast::ExpressionKind::Conditional(..) => return Fixity::infix(Token::Question),
+            _ => return None,
+        };
+        match operation {
+            Assert(_) => Fixity::prefix(Token::Plus),
+            BitwiseNot(_) => Fixity::prefix(Token::Tilde),
+            Negate(_) => Fixity::prefix(Token::Minus),
+            Not(_) => Fixity::prefix(Token::Not),
+            Factorial(_) => {
+                let operator = <PostfixOperator as Operator>::from(&Token::Exclamation)?;
+                Some(Fixity::Postfix(Token::Exclamation, operator.prec()))
+            }
+            Add(..) => Fixity::infix(Token::Plus),
+            And(..) => Fixity::infix(Token::And),
+            BitwiseAnd(..) => Fixity::infix(Token::Ampersand),
+            BitwiseOr(..) => Fixity::infix(Token::Pipe),
+            BitwiseXor(..) => Fixity::infix(Token::Xor),
+            Divide(..) => Fixity::infix(Token::Slash),
+            Equal(..) => Fixity::infix(Token::Equal),
+            Exponentiate(..) => Fixity::infix(Token::Caret),
+            GreaterThan(..) => Fixity::infix(Token::GreaterThan),
+            GreaterThanOrEqual(..) => Fixity::infix(Token::GreaterThanOrEqual),
+            LessThan(..) => Fixity::infix(Token::LessThan),
+            LessThanOrEqual(..) => Fixity::infix(Token::LessThanOrEqual),
+            Modulo(..) => Fixity::infix(Token::Percent),
+            Multiply(..) => Fixity::infix(Token::Asterisk),
+            NotEqual(..) => Fixity::infix(Token::LessOrGreaterThan),
+            Or(..) => Fixity::infix(Token::Or),
+            ShiftLeft(..) => Fixity::infix(Token::DoubleLessThan),
+            ShiftRight(..) => Fixity::infix(Token::DoubleGreaterThan),
+            Subtract(..) => Fixity::infix(Token::Minus),
+        }
     }
+
+    fn prefix(token: Token) -> Option<Fixity> {
+        let operator = <PrefixOperator as Operator>::from(&token)?;
+        Some(Fixity::Prefix(token, operator.prec()))
+    }
+
+    fn infix(token: Token) -> Option<Fixity> {
+        let operator = <InfixOperator as Operator>::from(&token)?;
+        Some(Fixity::Infix(token, operator.prec(), operator.assoc()))
+    }
+
+    /// Returns the precedence of the operator
+    pub(crate) fn prec(&self) -> u8 {
+        match self {
+            Fixity::Prefix(_, prec) | Fixity::Infix(_, prec, _) | Fixity::Postfix(_, prec) => *prec,
+        }
+    }
+}
+
+/// Returns the precedence of the infix operator a token is, when it follows
+/// an operand
+pub(crate) fn infix_prec(token: &Token) -> Option<u8> {
+    <InfixOperator as Operator>::from(token).map(|operator| operator.prec())
+}
+
+/// Parses the digits of a float literal, rejecting ones too large for a
+/// float, as infinity has no literal to print them back as
+fn parse_float(digits: &str) -> Option<f64> {
+    digits.parse().ok().filter(|x: &f64| x.is_finite())
 }
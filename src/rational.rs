@@ -0,0 +1,291 @@
+use crate::bigint::BigInt;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// An exact fraction of two integers.
+///
+/// Rationals are always normalised: the denominator is positive and shares no
+/// common factor with the numerator. The parts are kept on the heap so that
+/// rationals don't widen [`Value`](crate::Value) and every result carrying it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rational(Box<Parts>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Parts {
+    numer: BigInt,
+    denom: BigInt,
+}
+
+impl Rational {
+    /// Creates a normalised rational, or None if the denominator is zero
+    pub fn new(numer: BigInt, denom: BigInt) -> Option<Rational> {
+        if denom.is_zero() {
+            return None;
+        }
+        let gcd = gcd(numer.abs(), denom.abs());
+        let (numer, denom) = if denom.is_negative() {
+            (-numer, -denom)
+        } else {
+            (numer, denom)
+        };
+        Some(Rational::raw(
+            exact_div(&numer, &gcd),
+            exact_div(&denom, &gcd),
+        ))
+    }
+
+    /// Creates a rational from parts that are already normalised
+    fn raw(numer: BigInt, denom: BigInt) -> Rational {
+        Rational(Box::new(Parts { numer, denom }))
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.0.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.0.denom
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.0.denom == BigInt::from(1i64)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.numer.is_zero()
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational::raw(self.0.numer.abs(), self.0.denom.clone())
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.numer.to_f64() / self.0.denom.to_f64()
+    }
+
+    /// Rounds towards zero
+    pub fn trunc(&self) -> BigInt {
+        exact_div(&self.0.numer, &self.0.denom)
+    }
+
+    /// Rounds towards negative infinity
+    pub fn floor(&self) -> BigInt {
+        let trunc = self.trunc();
+        if self.0.numer.is_negative() && !self.is_integer() {
+            trunc - BigInt::from(1i64)
+        } else {
+            trunc
+        }
+    }
+
+    /// Rounds towards positive infinity
+    pub fn ceil(&self) -> BigInt {
+        let trunc = self.trunc();
+        if !self.0.numer.is_negative() && !self.is_integer() {
+            trunc + BigInt::from(1i64)
+        } else {
+            trunc
+        }
+    }
+
+    /// Rounds to the nearest integer, rounding half-way cases away from zero
+    pub fn round(&self) -> BigInt {
+        let half = Rational::raw(BigInt::from(1i64), BigInt::from(2i64));
+        if self.0.numer.is_negative() {
+            (self - &half).trunc()
+        } else {
+            (self + &half).trunc()
+        }
+    }
+
+    /// Raises the rational to an integer power, or None if it is zero and the
+    /// exponent is negative, or the exponent doesn't fit in a u32
+    pub fn pow(&self, exponent: i64) -> Option<Rational> {
+        let abs = u32::try_from(exponent.unsigned_abs()).ok()?;
+        let (numer, denom) = (self.0.numer.pow(abs), self.0.denom.pow(abs));
+        if exponent < 0 {
+            Rational::new(denom, numer)
+        } else {
+            Rational::new(numer, denom)
+        }
+    }
+
+    /// Returns the remainder of truncating division, which has the sign of the
+    /// dividend like the primitive integer types, or None if other is zero
+    pub fn rem(&self, other: &Rational) -> Option<Rational> {
+        let quotient = Rational::from((self / other)?.trunc());
+        Some(self - &(other * &quotient))
+    }
+
+    /// Formats the rational as a decimal number with the given number of
+    /// fractional digits, rounding half-way cases away from zero
+    pub fn to_decimal_string(&self, digits: usize) -> String {
+        let scale = BigInt::from(10i64).pow(digits as u32);
+        let scaled = Rational::raw(&self.0.numer * &scale, self.0.denom.clone()).round();
+        let mut s = scaled.abs().to_string();
+        if s.len() <= digits {
+            s = "0".repeat(digits - s.len() + 1) + &s;
+        }
+        if digits > 0 {
+            s.insert(s.len() - digits, '.');
+        }
+        if scaled.is_negative() {
+            s.insert(0, '-');
+        }
+        s
+    }
+}
+
+impl From<BigInt> for Rational {
+    fn from(i: BigInt) -> Self {
+        Rational::raw(i, BigInt::from(1i64))
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(i: i64) -> Self {
+        Rational::from(BigInt::from(i))
+    }
+}
+
+/// Displays the rational as `numer/denom`, or as a decimal expansion if a
+/// precision is given, e.g. `{:.3}` displays 1/3 as `0.333`.
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match f.precision() {
+            Some(digits) => f.write_str(&self.to_decimal_string(digits)),
+            None if self.is_integer() => write!(f, "{}", self.0.numer),
+            None => write!(f, "{}/{}", self.0.numer, self.0.denom),
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.0.numer * &other.0.denom).cmp(&(&other.0.numer * &self.0.denom))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational::raw(-self.0.numer, self.0.denom)
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        normalise(
+            &self.0.numer * &other.0.denom + &other.0.numer * &self.0.denom,
+            &self.0.denom * &other.0.denom,
+        )
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        normalise(
+            &self.0.numer * &other.0.denom - &other.0.numer * &self.0.denom,
+            &self.0.denom * &other.0.denom,
+        )
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        normalise(
+            &self.0.numer * &other.0.numer,
+            &self.0.denom * &other.0.denom,
+        )
+    }
+}
+
+/// Divides the rationals, or returns None if other is zero
+impl Div for &Rational {
+    type Output = Option<Rational>;
+
+    fn div(self, other: &Rational) -> Option<Rational> {
+        Rational::new(
+            &self.0.numer * &other.0.denom,
+            &self.0.denom * &other.0.numer,
+        )
+    }
+}
+
+/// Normalises a rational whose denominator is known to be positive
+fn normalise(numer: BigInt, denom: BigInt) -> Rational {
+    Rational::new(numer, denom).unwrap_or_else(|| unreachable!())
+}
+
+fn exact_div(a: &BigInt, b: &BigInt) -> BigInt {
+    a.div_rem(b).map(|(quot, _)| quot).unwrap_or_default()
+}
+
+/// Returns the greatest common divisor of two non-negative integers
+fn gcd(mut a: BigInt, mut b: BigInt) -> BigInt {
+    while !b.is_zero() {
+        let (_, rem) = a.div_rem(&b).unwrap_or_default();
+        a = b;
+        b = rem;
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rational(numer: i64, denom: i64) -> Rational {
+        Rational::new(BigInt::from(numer), BigInt::from(denom)).unwrap()
+    }
+
+    #[test]
+    fn test_rational() {
+        assert_eq!(rational(2, -4), rational(-1, 2));
+        assert_eq!(rational(2, -4).to_string(), "-1/2");
+        assert_eq!(rational(6, 3).to_string(), "2");
+        assert_eq!(rational(0, -3), Rational::from(0));
+        assert!(Rational::new(BigInt::from(1i64), BigInt::zero()).is_none());
+        assert_eq!(&rational(1, 10) + &rational(2, 10), rational(3, 10));
+        assert_eq!(&rational(1, 3) - &rational(1, 2), rational(-1, 6));
+        assert_eq!(&rational(2, 3) * &rational(3, 4), rational(1, 2));
+        assert_eq!(&rational(2, 3) / &rational(4, 3), Some(rational(1, 2)));
+        assert_eq!(&rational(2, 3) / &Rational::from(0), None);
+        assert_eq!(rational(-7, 2).rem(&rational(1, 1)), Some(rational(-1, 2)));
+        assert_eq!(rational(2, 3).pow(-2), Some(rational(9, 4)));
+        assert_eq!(Rational::from(0).pow(-1), None);
+        assert!(rational(1, 3) < rational(1, 2));
+        assert!(rational(-1, 2) < rational(-1, 3));
+    }
+
+    #[test]
+    fn test_rational_rounding() {
+        assert_eq!(rational(7, 2).floor(), BigInt::from(3i64));
+        assert_eq!(rational(-7, 2).floor(), BigInt::from(-4i64));
+        assert_eq!(rational(7, 2).ceil(), BigInt::from(4i64));
+        assert_eq!(rational(-7, 2).ceil(), BigInt::from(-3i64));
+        assert_eq!(rational(7, 2).round(), BigInt::from(4i64));
+        assert_eq!(rational(-7, 2).round(), BigInt::from(-4i64));
+        assert_eq!(rational(-7, 3).trunc(), BigInt::from(-2i64));
+        assert_eq!(format!("{:.3}", rational(1, 3)), "0.333");
+        assert_eq!(format!("{:.3}", rational(2, 3)), "0.667");
+        assert_eq!(format!("{:.2}", rational(-1, 200)), "-0.01");
+        assert_eq!(format!("{:.2}", rational(-1, 201)), "0.00");
+        assert_eq!(format!("{:.0}", rational(5, 2)), "3");
+        assert_eq!(format!("{:.1}", rational(1234, 10)), "123.4");
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+
+use crate::ast::Expression;
+use crate::error::Result;
+use crate::lexer::{Lexer, Token};
+use crate::parse::infix_prec;
+use crate::span::Span;
+
+/// The number of spaces continuation lines are indented by
+const INDENT: usize = 4;
+
+/// Formats the source text of expressions in a consistent style, e.g. to
+/// display formulas that users wrote:
+///
+/// ```
+/// use calculator_rs::SourceFormatter;
+///
+/// let formatter = SourceFormatter::new();
+/// assert_eq!(formatter.format("(1+1)*2+4!").unwrap(), "(1 + 1) * 2 + 4!");
+/// assert_eq!(formatter.format("((a))*(b^2)").unwrap(), "a * b ^ 2");
+/// ```
+///
+/// Infix operators are surrounded by single spaces, prefix operators and
+/// factorials are attached to their operands, and tokens are otherwise written
+/// as they are in the input, e.g. numbers keep their radix and digit
+/// separators. The input must parse, so that redundant parentheses can be
+/// found and long expressions wrapped between their operands. Comments are
+/// left out unless [`SourceFormatter::keep_comments`] is set.
+#[derive(Clone, Debug, Default)]
+pub struct SourceFormatter {
+    keep_comments: bool,
+    keep_parentheses: bool,
+    width: Option<usize>,
+}
+
+impl SourceFormatter {
+    pub fn new() -> SourceFormatter {
+        SourceFormatter::default()
+    }
+
+    /// Keeps the `#` comments of the input after the tokens they follow. As a
+    /// comment runs to the end of the line, the rest of the expression
+    /// continues on the next.
+    pub fn keep_comments(mut self, keep: bool) -> SourceFormatter {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// Keeps parentheses that the precedence of the operators makes
+    /// redundant, which may have been written for clarity
+    pub fn keep_parentheses(mut self, keep: bool) -> SourceFormatter {
+        self.keep_parentheses = keep;
+        self
+    }
+
+    /// Wraps expressions longer than the width over several lines, breaking
+    /// before the most loosely binding operators or between the arguments of
+    /// calls. Continuation lines are indented by four spaces.
+    pub fn width(mut self, width: usize) -> SourceFormatter {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn format(&self, input: &str) -> Result<String> {
+        let expression = crate::parse(input)?;
+        let lexer = if self.keep_comments {
+            Lexer::new(input).with_comments()
+        } else {
+            Lexer::new(input)
+        };
+        let tokens = lexer.collect::<Result<Vec<_>>>()?;
+        let redundant = if self.keep_parentheses {
+            HashSet::new()
+        } else {
+            redundant_parentheses(&expression, &tokens)
+        };
+        let words = words(tokens, &redundant);
+        let mut lines = Vec::new();
+        self.wrap(&words, 0, &mut lines);
+        Ok(lines.join("\n"))
+    }
+
+    /// Writes the words on lines no longer than the width where possible,
+    /// starting at the indentation
+    fn wrap(&self, words: &[Word], indent: usize, lines: &mut Vec<String>) {
+        // A comment ends the line, and the lines after comments that follow
+        // part of the expression are continuations
+        let comments = words[..words.len() - 1]
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| matches!(word.token, Token::Comment(_)));
+        let ends: Vec<_> = comments.map(|(i, _)| i + 1).collect();
+        if !ends.is_empty() {
+            let mut start = 0;
+            for end in ends.into_iter().chain([words.len()]) {
+                let continued = words[..start]
+                    .iter()
+                    .any(|word| !matches!(word.token, Token::Comment(_)));
+                let indent = if continued { indent + INDENT } else { indent };
+                self.wrap(&words[start..end], indent, lines);
+                start = end;
+            }
+            return;
+        }
+        let line = render(words);
+        let fits = self
+            .width
+            .is_none_or(|width| indent + line.chars().count() <= width);
+        if fits || words.len() == 1 {
+            lines.push(" ".repeat(indent) + &line);
+            return;
+        }
+        // Break before the most loosely binding operators outside parentheses
+        let mut depth = 0usize;
+        let mut breaks = Vec::new();
+        let mut loosest = u8::MAX;
+        for (i, word) in words.iter().enumerate() {
+            match word.token {
+                Token::OpenParen => depth += 1,
+                Token::CloseParen => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            match word.infix {
+                Some(prec) if depth == 0 && i > 0 && prec < loosest => {
+                    loosest = prec;
+                    breaks = vec![i];
+                }
+                Some(prec) if depth == 0 && i > 0 && prec == loosest => breaks.push(i),
+                _ => {}
+            }
+        }
+        if !breaks.is_empty() {
+            let mut start = 0;
+            for end in breaks.into_iter().chain([words.len()]) {
+                let indent = if start == 0 { indent } else { indent + INDENT };
+                self.wrap(&words[start..end], indent, lines);
+                start = end;
+            }
+            return;
+        }
+        // Otherwise put what the first parentheses enclose on lines of its
+        // own, with an argument of a call on each
+        let open = words.iter().position(|word| word.token == Token::OpenParen);
+        match open.and_then(|open| Some((open, closing_parenthesis(words, open)?))) {
+            Some((open, close)) if close > open + 1 => {
+                lines.push(" ".repeat(indent) + &render(&words[..=open]));
+                let mut depth = 0usize;
+                let mut start = open + 1;
+                for i in open + 1..close {
+                    match words[i].token {
+                        Token::OpenParen => depth += 1,
+                        Token::CloseParen => depth -= 1,
+                        Token::Comma if depth == 0 => {
+                            self.wrap(&words[start..=i], indent + INDENT, lines);
+                            start = i + 1;
+                        }
+                        _ => {}
+                    }
+                }
+                self.wrap(&words[start..close], indent + INDENT, lines);
+                self.wrap(&words[close..], indent, lines);
+            }
+            _ => lines.push(" ".repeat(indent) + &line),
+        }
+    }
+}
+
+/// A token of the formatted expression
+struct Word {
+    token: Token,
+    /// Whether a space separates it from the previous word
+    space: bool,
+    /// The precedence of the token if it is an infix operator, or the `:` of
+    /// a conditional
+    infix: Option<u8>,
+}
+
+/// Spaces the tokens, leaving out the redundant parentheses
+fn words(tokens: Vec<(Token, Span)>, redundant: &HashSet<usize>) -> Vec<Word> {
+    let mut words: Vec<Word> = Vec::new();
+    for (i, (token, _)) in tokens.into_iter().enumerate() {
+        if redundant.contains(&i) {
+            continue;
+        }
+        let previous = words
+            .iter()
+            .rev()
+            .find(|word| !matches!(word.token, Token::Comment(_)));
+        // A `+` or `-` is an infix operator if it follows an operand, and
+        // otherwise a prefix operator
+        let follows_operand = previous.is_some_and(|word| {
+            matches!(
+                word.token,
+                Token::Number(_)
+                    | Token::Ident(_)
+                    | Token::True
+                    | Token::False
+                    | Token::CloseParen
+                    | Token::Exclamation
+            )
+        });
+        let infix = match &token {
+            Token::Colon => infix_prec(&Token::Question),
+            token if follows_operand => infix_prec(token),
+            _ => None,
+        };
+        let space = match previous {
+            _ if matches!(token, Token::Comment(_)) => !words.is_empty(),
+            None => false,
+            Some(_) if matches!(token, Token::CloseParen | Token::Comma | Token::Exclamation) => {
+                false
+            }
+            Some(word) => match word.token {
+                Token::OpenParen => false,
+                Token::Ident(_) => token != Token::OpenParen,
+                // Prefix operators other than `not` are attached to their
+                // operand, but a stacked `-` or `+` is kept apart so that the
+                // pair does not read as `--` or `++`
+                Token::Minus | Token::Plus => word.infix.is_some() || token == word.token,
+                Token::Tilde => word.infix.is_some(),
+                _ => true,
+            },
+        };
+        words.push(Word {
+            token,
+            space,
+            infix,
+        });
+    }
+    words
+}
+
+fn render(words: &[Word]) -> String {
+    let mut line = String::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 && word.space {
+            line.push(' ');
+        }
+        line.push_str(&word.token.to_string());
+    }
+    line
+}
+
+/// Returns the index of the parenthesis closing the one at `open`
+fn closing_parenthesis(words: &[Word], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, word) in words.iter().enumerate().skip(open) {
+        match word.token {
+            Token::OpenParen => depth += 1,
+            Token::CloseParen => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the indices of the parentheses that the precedence of the
+/// operators makes redundant
+fn redundant_parentheses(expression: &Expression, tokens: &[(Token, Span)]) -> HashSet<usize> {
+    // The spans of the expressions that must be parenthesized, which the
+    // parser extends over their parentheses
+    let mut required = HashSet::new();
+    let mut stack = vec![(expression, 0)];
+    while let Some((expression, min_prec)) = stack.pop() {
+        if expression.prec() < min_prec {
+            required.insert(expression.span);
+        }
+        stack.extend(expression.operand_precs());
+    }
+    let mut redundant = HashSet::new();
+    let mut open = Vec::new();
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        match token {
+            Token::OpenParen => {
+                // The parentheses around the arguments of a call are needed
+                let previous = tokens[..i]
+                    .iter()
+                    .rev()
+                    .find(|(token, _)| !matches!(token, Token::Comment(_)));
+                let call = matches!(previous, Some((Token::Ident(_), _)));
+                open.push((i, call));
+            }
+            Token::CloseParen => {
+                if let Some((start, call)) = open.pop() {
+                    if !call && !required.contains(&tokens[start].1.join(*span)) {
+                        redundant.extend([start, i]);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    redundant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let format = |input: &str| SourceFormatter::new().format(input);
+        assert_eq!(format("(1+1)*2+4!").unwrap(), "(1 + 1) * 2 + 4!");
+        assert_eq!(format("  a+-b  *  ~c ").unwrap(), "a + -b * ~c");
+        assert_eq!(format("1 - -(-2)").unwrap(), "1 - - -2");
+        assert_eq!(format("+(+a) - (-b)").unwrap(), "+ +a - -b");
+        assert_eq!(format("not(a)&&b||c").unwrap(), "not a && b || c");
+        assert_eq!(format("x?y:z").unwrap(), "x ? y : z");
+        assert_eq!(format("max ( 1,(2) ,f() )").unwrap(), "max(1, 2, f())");
+        assert_eq!(format("((a-b))-(c-d)").unwrap(), "a - b - (c - d)");
+        assert_eq!(format("a-(b-(c))").unwrap(), "a - (b - c)");
+        assert_eq!(
+            format("(2^3)^4 + 2^(3^4)").unwrap(),
+            "(2 ^ 3) ^ 4 + 2 ^ 3 ^ 4"
+        );
+        assert_eq!(format("-(2^2) + (-2)^2").unwrap(), "-(2 ^ 2) + -2 ^ 2");
+        assert_eq!(format("(3)! =6").unwrap(), "3! = 6");
+        assert_eq!(format("(3)!=6").unwrap(), "3 != 6");
+        assert_eq!(
+            format("0X_FF+1_000.5e-3d*2i").unwrap(),
+            "0X_FF + 1_000.5e-3d * 2i"
+        );
+        assert_eq!(format("1 +").unwrap_err().code(), "E0003");
+
+        assert_eq!(format("1 + # one\n2").unwrap(), "1 + 2");
+
+        let keep = |input: &str| SourceFormatter::new().keep_parentheses(true).format(input);
+        assert_eq!(keep("((a))*(b^2)").unwrap(), "((a)) * (b ^ 2)");
+        assert_eq!(keep("-( 1 )!").unwrap(), "-(1)!");
+    }
+
+    #[test]
+    fn test_comments() {
+        let format = |input: &str| SourceFormatter::new().keep_comments(true).format(input);
+        assert_eq!(format("1+1 # two").unwrap(), "1 + 1 # two");
+        assert_eq!(
+            format("# the total\nprice*qty  # before tax  \n+tax").unwrap(),
+            "# the total\nprice * qty # before tax\n    + tax"
+        );
+        assert_eq!(format("max(# a\n(1),2)").unwrap(), "max( # a\n    1, 2)");
+        let format = |input: &str| {
+            SourceFormatter::new()
+                .keep_comments(true)
+                .width(22)
+                .format(input)
+        };
+        assert_eq!(
+            format("net * rate # per unit\n+ shipping_cost * 2").unwrap(),
+            "net * rate # per unit\n    + shipping_cost\n        * 2"
+        );
+    }
+
+    #[test]
+    fn test_width() {
+        let format =
+            |width: usize, input: &str| SourceFormatter::new().width(width).format(input).unwrap();
+        let input = "price * quantity + shipping_cost - discount * price";
+        assert_eq!(format(60, input), input);
+        assert_eq!(
+            format(30, input),
+            "price * quantity\n    + shipping_cost\n    - discount * price"
+        );
+        assert_eq!(
+            format(20, "total > 100 and member ? price * 0.9 : price"),
+            "total > 100\n    and member\n    ? price * 0.9\n    : price"
+        );
+        assert_eq!(
+            format(20, "round(subtotal * rate, 2) + fee"),
+            "round(\n    subtotal * rate,\n    2\n)\n    + fee"
+        );
+        // Words longer than the width are left on their own lines
+        assert_eq!(format(4, "-very_long_name!"), "-very_long_name!");
+    }
+}
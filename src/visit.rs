@@ -0,0 +1,301 @@
+//! Traversals of the [`ast`](crate::ast), for writing analyses and rewrites
+//! that only handle the expressions they care about.
+//!
+//! Each trait has a method per kind of expression, whose default walks into
+//! its operands from left to right. Overriding a method replaces the walk, so
+//! an override that should still reach nested expressions calls the
+//! corresponding `walk_*` or `fold_*` function:
+//!
+//! ```
+//! use calculator_rs::ast::Operation;
+//! use calculator_rs::visit::{walk_operation, Visitor};
+//! use calculator_rs::Span;
+//!
+//! #[derive(Default)]
+//! struct CountOperations(usize);
+//!
+//! impl Visitor for CountOperations {
+//!     fn visit_operation(&mut self, operation: &Operation, span: Span) {
+//!         self.0 += 1;
+//!         walk_operation(self, operation, span);
+//!     }
+//! }
+//!
+//! let mut count = CountOperations::default();
+//! count.visit_expression(&calculator_rs::parse("-(1 + 2) * 3!").unwrap());
+//! assert_eq!(count.0, 4);
+//! ```
+//!
+//! The walks recurse, which parsed expressions are shallow enough for.
+
+use crate::ast::{Expression, ExpressionKind, Literal, Operation};
+use crate::span::Span;
+
+/// Visits an expression by reference
+pub trait Visitor {
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+
+    fn visit_call(&mut self, _name: &str, args: &[Expression], _span: Span) {
+        for arg in args {
+            self.visit_expression(arg);
+        }
+    }
+
+    fn visit_conditional(
+        &mut self,
+        condition: &Expression,
+        then: &Expression,
+        otherwise: &Expression,
+        _span: Span,
+    ) {
+        self.visit_expression(condition);
+        self.visit_expression(then);
+        self.visit_expression(otherwise);
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal, _span: Span) {}
+
+    fn visit_operation(&mut self, operation: &Operation, span: Span) {
+        walk_operation(self, operation, span)
+    }
+
+    fn visit_variable(&mut self, _name: &str, _span: Span) {}
+}
+
+/// Calls the visitor's method for the kind of expression
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    let span = expression.span;
+    match &expression.kind {
+        ExpressionKind::Call(name, args) => visitor.visit_call(name, args, span),
+        ExpressionKind::Conditional(condition, then, otherwise) => {
+            visitor.visit_conditional(condition, then, otherwise, span)
+        }
+        ExpressionKind::Literal(literal) => visitor.visit_literal(literal, span),
+        ExpressionKind::Operation(operation) => visitor.visit_operation(operation, span),
+        ExpressionKind::Variable(name) => visitor.visit_variable(name, span),
+    }
+}
+
+/// Visits the operands of an operation
+pub fn walk_operation<V: Visitor + ?Sized>(visitor: &mut V, operation: &Operation, _span: Span) {
+    for operand in operation.operands() {
+        visitor.visit_expression(operand);
+    }
+}
+
+/// Visits an expression by mutable reference, to rewrite it in place
+pub trait MutVisitor {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression)
+    }
+
+    fn visit_call_mut(&mut self, _name: &mut String, args: &mut Vec<Expression>, _span: Span) {
+        for arg in args {
+            self.visit_expression_mut(arg);
+        }
+    }
+
+    fn visit_conditional_mut(
+        &mut self,
+        condition: &mut Expression,
+        then: &mut Expression,
+        otherwise: &mut Expression,
+        _span: Span,
+    ) {
+        self.visit_expression_mut(condition);
+        self.visit_expression_mut(then);
+        self.visit_expression_mut(otherwise);
+    }
+
+    fn visit_literal_mut(&mut self, _literal: &mut Literal, _span: Span) {}
+
+    fn visit_operation_mut(&mut self, operation: &mut Operation, span: Span) {
+        walk_operation_mut(self, operation, span)
+    }
+
+    fn visit_variable_mut(&mut self, _name: &mut String, _span: Span) {}
+}
+
+/// Calls the mutable visitor's method for the kind of expression
+pub fn walk_expression_mut<V: MutVisitor + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    let span = expression.span;
+    match &mut expression.kind {
+        ExpressionKind::Call(name, args) => visitor.visit_call_mut(name, args, span),
+        ExpressionKind::Conditional(condition, then, otherwise) => {
+            visitor.visit_conditional_mut(condition, then, otherwise, span)
+        }
+        ExpressionKind::Literal(literal) => visitor.visit_literal_mut(literal, span),
+        ExpressionKind::Operation(operation) => visitor.visit_operation_mut(operation, span),
+        ExpressionKind::Variable(name) => visitor.visit_variable_mut(name, span),
+    }
+}
+
+/// Visits the operands of an operation mutably
+pub fn walk_operation_mut<V: MutVisitor + ?Sized>(
+    visitor: &mut V,
+    operation: &mut Operation,
+    _span: Span,
+) {
+    for operand in operation.operands_mut() {
+        visitor.visit_expression_mut(operand);
+    }
+}
+
+/// Rebuilds an expression by value, so each kind of expression can be
+/// replaced with any other, e.g. an operation with the literal it evaluates to
+pub trait Fold {
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        fold_expression(self, expression)
+    }
+
+    fn fold_call(&mut self, name: String, args: Vec<Expression>, span: Span) -> Expression {
+        let args = args
+            .into_iter()
+            .map(|arg| self.fold_expression(arg))
+            .collect();
+        Expression::new(ExpressionKind::Call(name, args), span)
+    }
+
+    fn fold_conditional(
+        &mut self,
+        condition: Expression,
+        then: Expression,
+        otherwise: Expression,
+        span: Span,
+    ) -> Expression {
+        let kind = ExpressionKind::Conditional(
+            Box::new(self.fold_expression(condition)),
+            Box::new(self.fold_expression(then)),
+            Box::new(self.fold_expression(otherwise)),
+        );
+        Expression::new(kind, span)
+    }
+
+    fn fold_literal(&mut self, literal: Literal, span: Span) -> Expression {
+        Expression::new(literal, span)
+    }
+
+    fn fold_operation(&mut self, operation: Operation, span: Span) -> Expression {
+        fold_operation(self, operation, span)
+    }
+
+    fn fold_variable(&mut self, name: String, span: Span) -> Expression {
+        Expression::new(ExpressionKind::Variable(name), span)
+    }
+}
+
+/// Calls the folder's method for the kind of expression
+pub fn fold_expression<F: Fold + ?Sized>(folder: &mut F, expression: Expression) -> Expression {
+    let Expression { kind, span } = expression;
+    match kind {
+        ExpressionKind::Call(name, args) => folder.fold_call(name, args, span),
+        ExpressionKind::Conditional(condition, then, otherwise) => {
+            folder.fold_conditional(*condition, *then, *otherwise, span)
+        }
+        ExpressionKind::Literal(literal) => folder.fold_literal(literal, span),
+        ExpressionKind::Operation(operation) => folder.fold_operation(operation, span),
+        ExpressionKind::Variable(name) => folder.fold_variable(name, span),
+    }
+}
+
+/// Folds the operands of an operation, keeping the operation
+pub fn fold_operation<F: Fold + ?Sized>(
+    folder: &mut F,
+    operation: Operation,
+    span: Span,
+) -> Expression {
+    let operation = operation.map_operands(|operand| folder.fold_expression(operand));
+    Expression::new(operation, span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Calculator, Context, Value};
+
+    #[derive(Default)]
+    struct Variables(Vec<String>);
+
+    impl Visitor for Variables {
+        fn visit_variable(&mut self, name: &str, _span: Span) {
+            self.0.push(name.into());
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let mut variables = Variables::default();
+        variables.visit_expression(&parse("max(a, b ? -c! : d) * (e + f) > g").unwrap());
+        assert_eq!(variables.0, ["a", "b", "c", "d", "e", "f", "g"]);
+
+        // An override that doesn't walk stops at the expression
+        struct Calls(usize);
+        impl Visitor for Calls {
+            fn visit_call(&mut self, _name: &str, _args: &[Expression], _span: Span) {
+                self.0 += 1;
+            }
+        }
+        let mut calls = Calls(0);
+        calls.visit_expression(&parse("abs(abs(1)) + abs(2)").unwrap());
+        assert_eq!(calls.0, 2);
+    }
+
+    #[test]
+    fn test_mut_visitor() {
+        struct Rename;
+        impl MutVisitor for Rename {
+            fn visit_variable_mut(&mut self, name: &mut String, _span: Span) {
+                name.make_ascii_uppercase();
+            }
+        }
+        let mut expression = parse("a + b * c").unwrap();
+        Rename.visit_expression_mut(&mut expression);
+        let mut variables = Variables::default();
+        variables.visit_expression(&expression);
+        assert_eq!(variables.0, ["A", "B", "C"]);
+    }
+
+    #[test]
+    fn test_fold() {
+        /// Replaces operations on literals with their value, where it is an
+        /// integer
+        struct ConstantFold(Calculator);
+        impl Fold for ConstantFold {
+            fn fold_operation(&mut self, operation: Operation, span: Span) -> Expression {
+                let expression = fold_operation(self, operation, span);
+                let constant = expression
+                    .kind
+                    .operands()
+                    .iter()
+                    .all(|operand| matches!(operand.kind, ExpressionKind::Literal(_)));
+                match self.0.evaluate(&expression, &Context::new()) {
+                    Ok(Value::Integer(i)) if constant => Expression::new(Literal::Integer(i), span),
+                    _ => expression,
+                }
+            }
+        }
+        let mut folder = ConstantFold(Calculator::new());
+        let expression = folder.fold_expression(parse("x * (2 + 3 * 4) - -(1 / 2)").unwrap());
+        let expected = parse("x * 14 - -(1 / 2)").unwrap();
+        let mut variables = Variables::default();
+        variables.visit_expression(&expression);
+        assert_eq!(variables.0, ["x"]);
+        let context = Context::new().with("x", 3);
+        assert_eq!(
+            Calculator::new().evaluate(&expression, &context),
+            Calculator::new().evaluate(&expected, &context)
+        );
+        match &expression.kind {
+            ExpressionKind::Operation(Operation::Subtract(lhs, _)) => match &lhs.kind {
+                ExpressionKind::Operation(Operation::Multiply(_, rhs)) => {
+                    assert_eq!(rhs.kind, ExpressionKind::Literal(Literal::Integer(14)));
+                    assert_eq!(rhs.span, Span::new(4, 15));
+                }
+                kind => panic!("unexpected {:?}", kind),
+            },
+            kind => panic!("unexpected {:?}", kind),
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use crate::ast::{Expression, ExpressionKind, Operation};
+use crate::context::Context;
+use crate::error::{Error, Result, ValueError};
+use crate::limits::Fuel;
+use crate::span::Span;
+use crate::{Calculator, Value};
+
+/// An instruction of the stack machine. Operations pop their operands off the
+/// stack, with the right operand on top, and push their result.
+#[derive(Clone, Debug, PartialEq)]
+enum Instruction {
+    Push(Value),
+    Load(String),
+    /// Calls a function with the given number of arguments
+    Call(String, usize),
+    /// Fails if the value on top of the stack isn't a boolean, as an operand
+    /// of the given operation
+    Expect(&'static str),
+    /// Jumps to the instruction at the index
+    Jump(usize),
+    /// Pops a boolean and jumps if it is false
+    JumpIfFalse(usize),
+    /// Jumps if the boolean on top of the stack is false, or else pops it
+    JumpIfFalseOrPop(usize),
+    /// Jumps if the boolean on top of the stack is true, or else pops it
+    JumpIfTrueOrPop(usize),
+    Add,
+    BitwiseAnd,
+    BitwiseNot,
+    BitwiseOr,
+    BitwiseXor,
+    Divide,
+    Equal,
+    Exponentiate,
+    Factorial,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    Modulo,
+    Multiply,
+    Negate,
+    Not,
+    NotEqual,
+    ShiftLeft,
+    ShiftRight,
+    Subtract,
+}
+
+/// An expression compiled to instructions for the stack machine, each with
+/// the span of the expression it came from to locate errors
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Program {
+    instructions: Vec<(Instruction, Span)>,
+    /// The spans of the expressions evaluation enters before each
+    /// instruction, which each take a step of fuel as in the tree-walking
+    /// backend
+    entered: Vec<Vec<Span>>,
+}
+
+/// A step of compiling an expression, kept on an explicit stack rather than
+/// the call stack so deeply nested expressions can be compiled
+enum Task<'a> {
+    Compile(&'a Expression),
+    Emit(Instruction, Span),
+    /// Marks the current position as the target of the label
+    Label(usize),
+}
+
+impl Program {
+    pub(crate) fn compile(expression: &Expression) -> Program {
+        let mut instructions = Vec::new();
+        let mut entered = Vec::new();
+        let mut entering = Vec::new();
+        let mut labels = Vec::new();
+        let mut tasks = vec![Task::Compile(expression)];
+        while let Some(task) = tasks.pop() {
+            let expression = match task {
+                Task::Compile(expression) => expression,
+                Task::Emit(instruction, span) => {
+                    instructions.push((instruction, span));
+                    entered.push(std::mem::take(&mut entering));
+                    continue;
+                }
+                Task::Label(label) => {
+                    labels[label] = instructions.len();
+                    continue;
+                }
+            };
+            let span = expression.span;
+            // An expression is entered before its first instruction, which
+            // follows directly as every expression starts with an operand or
+            // emits an instruction
+            entering.push(span);
+            // Tasks are popped in reverse, so they are pushed last to first
+            match &expression.kind {
+                ExpressionKind::Call(name, args) => {
+                    tasks.push(Task::Emit(
+                        Instruction::Call(name.clone(), args.len()),
+                        span,
+                    ));
+                    tasks.extend(args.iter().rev().map(Task::Compile));
+                }
+                ExpressionKind::Conditional(condition, then, otherwise) => {
+                    let (other, end) = (labels.len(), labels.len() + 1);
+                    labels.extend([0, 0]);
+                    tasks.extend([
+                        Task::Label(end),
+                        Task::Compile(otherwise),
+                        Task::Label(other),
+                        Task::Emit(Instruction::Jump(end), span),
+                        Task::Compile(then),
+                        Task::Emit(Instruction::JumpIfFalse(other), condition.span),
+                        Task::Emit(Instruction::Expect("branch on"), condition.span),
+                        Task::Compile(condition),
+                    ]);
+                }
+                ExpressionKind::Literal(literal) => {
+                    instructions.push((Instruction::Push(literal.clone().into()), span));
+                    entered.push(std::mem::take(&mut entering));
+                }
+                ExpressionKind::Variable(name) => {
+                    instructions.push((Instruction::Load(name.clone()), span));
+                    entered.push(std::mem::take(&mut entering));
+                }
+                ExpressionKind::Operation(operation) => {
+                    let (instruction, lhs, rhs) = match operation {
+                        Operation::And(lhs, rhs) | Operation::Or(lhs, rhs) => {
+                            let (operation, jump): (_, fn(usize) -> Instruction) = match operation {
+                                Operation::And(..) => {
+                                    ("apply and to", Instruction::JumpIfFalseOrPop)
+                                }
+                                _ => ("apply or to", Instruction::JumpIfTrueOrPop),
+                            };
+                            let end = labels.len();
+                            labels.push(0);
+                            tasks.extend([
+                                Task::Label(end),
+                                Task::Emit(Instruction::Expect(operation), rhs.span),
+                                Task::Compile(rhs),
+                                Task::Emit(jump(end), lhs.span),
+                                Task::Emit(Instruction::Expect(operation), lhs.span),
+                                Task::Compile(lhs),
+                            ]);
+                            continue;
+                        }
+                        // Asserting a value leaves it unchanged
+                        Operation::Assert(lhs) => {
+                            tasks.push(Task::Compile(lhs));
+                            continue;
+                        }
+                        Operation::Not(lhs) => (Instruction::Not, lhs, None),
+                        Operation::BitwiseNot(lhs) => (Instruction::BitwiseNot, lhs, None),
+                        Operation::Factorial(lhs) => (Instruction::Factorial, lhs, None),
+                        Operation::Negate(lhs) => (Instruction::Negate, lhs, None),
+                        Operation::Add(lhs, rhs) => (Instruction::Add, lhs, Some(rhs)),
+                        Operation::BitwiseAnd(lhs, rhs) => {
+                            (Instruction::BitwiseAnd, lhs, Some(rhs))
+                        }
+                        Operation::BitwiseOr(lhs, rhs) => (Instruction::BitwiseOr, lhs, Some(rhs)),
+                        Operation::BitwiseXor(lhs, rhs) => {
+                            (Instruction::BitwiseXor, lhs, Some(rhs))
+                        }
+                        Operation::Divide(lhs, rhs) => (Instruction::Divide, lhs, Some(rhs)),
+                        Operation::Equal(lhs, rhs) => (Instruction::Equal, lhs, Some(rhs)),
+                        Operation::Exponentiate(lhs, rhs) => {
+                            (Instruction::Exponentiate, lhs, Some(rhs))
+                        }
+                        Operation::GreaterThan(lhs, rhs) => {
+                            (Instruction::GreaterThan, lhs, Some(rhs))
+                        }
+                        Operation::GreaterThanOrEqual(lhs, rhs) => {
+                            (Instruction::GreaterThanOrEqual, lhs, Some(rhs))
+                        }
+                        Operation::LessThan(lhs, rhs) => (Instruction::LessThan, lhs, Some(rhs)),
+                        Operation::LessThanOrEqual(lhs, rhs) => {
+                            (Instruction::LessThanOrEqual, lhs, Some(rhs))
+                        }
+                        Operation::Modulo(lhs, rhs) => (Instruction::Modulo, lhs, Some(rhs)),
+                        Operation::Multiply(lhs, rhs) => (Instruction::Multiply, lhs, Some(rhs)),
+                        Operation::NotEqual(lhs, rhs) => (Instruction::NotEqual, lhs, Some(rhs)),
+                        Operation::ShiftLeft(lhs, rhs) => (Instruction::ShiftLeft, lhs, Some(rhs)),
+                        Operation::ShiftRight(lhs, rhs) => {
+                            (Instruction::ShiftRight, lhs, Some(rhs))
+                        }
+                        Operation::Subtract(lhs, rhs) => (Instruction::Subtract, lhs, Some(rhs)),
+                    };
+                    // The operand of `not` locates its error, as with the
+                    // other logical operators
+                    let span = match instruction {
+                        Instruction::Not => lhs.span,
+                        _ => span,
+                    };
+                    tasks.push(Task::Emit(instruction, span));
+                    tasks.extend(rhs.map(|rhs| Task::Compile(rhs)));
+                    tasks.push(Task::Compile(lhs));
+                }
+            }
+        }
+        for (instruction, _) in &mut instructions {
+            if let Instruction::Jump(target)
+            | Instruction::JumpIfFalse(target)
+            | Instruction::JumpIfFalseOrPop(target)
+            | Instruction::JumpIfTrueOrPop(target) = instruction
+            {
+                *target = labels[*target];
+            }
+        }
+        Program {
+            instructions,
+            entered,
+        }
+    }
+}
+
+impl Calculator {
+    /// Executes a compiled expression, giving the same results and errors as
+    /// calculating the expression it was compiled from
+    pub(crate) fn execute(&self, program: &Program, context: &Context) -> Result<Value> {
+        let mut stack = Vec::new();
+        let mut pc = 0;
+        let mut fuel = Fuel::new(&self.limits);
+        while let Some((instruction, span)) = program.instructions.get(pc) {
+            for entered in &program.entered[pc] {
+                fuel.burn(1).map_err(|err| err.or_span(*entered))?;
+            }
+            pc += 1;
+            self.step(instruction, &mut stack, &mut pc, context, &mut fuel)
+                .map_err(|err| err.or_span(*span))?;
+        }
+        Ok(pop(&mut stack))
+    }
+
+    fn step(
+        &self,
+        instruction: &Instruction,
+        stack: &mut Vec<Value>,
+        pc: &mut usize,
+        context: &Context,
+        fuel: &mut Fuel,
+    ) -> Result<()> {
+        let value = match instruction {
+            Instruction::Push(value) => value.clone(),
+            Instruction::Load(name) => Self::variable(name, context)?,
+            Instruction::Call(name, args) => {
+                let args = stack.split_off(stack.len() - args);
+                self.call(name, args)?
+            }
+            Instruction::Expect(operation) => {
+                if !matches!(stack.last(), Some(Value::Boolean(_))) {
+                    return Err(Error::from(ValueError::InvalidOperand {
+                        operation,
+                        value: pop(stack),
+                    }));
+                }
+                return Ok(());
+            }
+            Instruction::Jump(target) => {
+                *pc = *target;
+                return Ok(());
+            }
+            Instruction::JumpIfFalse(target) => {
+                if pop(stack) == Value::Boolean(false) {
+                    *pc = *target;
+                }
+                return Ok(());
+            }
+            Instruction::JumpIfFalseOrPop(target) | Instruction::JumpIfTrueOrPop(target) => {
+                let jump = matches!(instruction, Instruction::JumpIfTrueOrPop(_));
+                if stack.last() == Some(&Value::Boolean(jump)) {
+                    *pc = *target;
+                } else {
+                    stack.pop();
+                }
+                return Ok(());
+            }
+            Instruction::BitwiseNot => self.bitwise_not(pop(stack), fuel)?,
+            Instruction::Factorial => self.factorial(pop(stack), fuel)?,
+            Instruction::Negate => self.negate(pop(stack))?,
+            Instruction::Not => match pop(stack) {
+                Value::Boolean(b) => Value::Boolean(!b),
+                value => {
+                    return Err(ValueError::InvalidOperand {
+                        operation: "apply not to",
+                        value,
+                    }
+                    .into());
+                }
+            },
+            binary => {
+                let rhs = pop(stack);
+                let lhs = pop(stack);
+                match binary {
+                    Instruction::Add => self.add(lhs, rhs, fuel)?,
+                    Instruction::BitwiseAnd => self.bitwise_and(lhs, rhs, fuel)?,
+                    Instruction::BitwiseOr => self.bitwise_or(lhs, rhs, fuel)?,
+                    Instruction::BitwiseXor => self.bitwise_xor(lhs, rhs, fuel)?,
+                    Instruction::Divide => self.divide(lhs, rhs, fuel)?,
+                    Instruction::Equal => Self::equal(lhs, rhs)?,
+                    Instruction::Exponentiate => self.exponentiate(lhs, rhs, fuel)?,
+                    Instruction::GreaterThan => Self::greater_than(lhs, rhs)?,
+                    Instruction::GreaterThanOrEqual => Self::greater_than_or_equal(lhs, rhs)?,
+                    Instruction::LessThan => Self::less_than(lhs, rhs)?,
+                    Instruction::LessThanOrEqual => Self::less_than_or_equal(lhs, rhs)?,
+                    Instruction::Modulo => self.modulo(lhs, rhs, fuel)?,
+                    Instruction::Multiply => self.multiply(lhs, rhs, fuel)?,
+                    Instruction::NotEqual => Self::not_equal(lhs, rhs)?,
+                    Instruction::ShiftLeft => self.shift_left(lhs, rhs, fuel)?,
+                    Instruction::ShiftRight => self.shift_right(lhs, rhs)?,
+                    Instruction::Subtract => self.subtract(lhs, rhs, fuel)?,
+                    _ => unreachable!("{:?} is not a binary operation", binary),
+                }
+            }
+        };
+        stack.push(value);
+        Ok(())
+    }
+}
+
+/// Pops an operand, which the compiler guarantees is on the stack
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack
+        .pop()
+        .expect("the stack has an operand for every instruction")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::Parser;
+    use crate::{Backend, EvalLimits};
+
+    /// Inputs covering every instruction, along with their errors
+    const INPUTS: &[&str] = &[
+        "(1+1)*2+4!",
+        "(1.1+1.1)*2+4!",
+        "1*!1",
+        "31%15",
+        "30!",
+        "-3!",
+        "+-+5",
+        "2^200 / 2^190",
+        "9223372036854775807 + 1",
+        "-(-9223372036854775807 - 1)",
+        "7 / 2 - 1/3",
+        "(2/3)^-2",
+        "0^-1",
+        "1 / (1/2 - 1/2)",
+        "10d / 3",
+        "1.5d ^ 2",
+        "-7.5d % 2",
+        "(1+2i) / (3+4i)",
+        "(-8)^(1/3)",
+        "(1+1i) % 2",
+        "0xF0 | 0x0F xor 3 & ~1",
+        "1 << 70 >> 68",
+        "1 << -1",
+        "~1.5",
+        "1 = 1 and 2 <> 3",
+        "1 < 2 <= 3",
+        "2 > 1 >= 0",
+        "not 1 = 2 or x",
+        "false and 1 / 0",
+        "true or unknown",
+        "true and 1",
+        "false or 1",
+        "not 0",
+        "true + 1",
+        "x = 0 ? 0 : 1 / x",
+        "y = 0 ? 0 : 1 / y",
+        "x > 0 ? 1 : x < 0 ? -1 : 0",
+        "y ? 1 : 2",
+        "true ? false ? 1 : 2 : 3",
+        "sqrt(16) + abs(-3) * max(1, y, 2.5)",
+        "min()",
+        "sqrt(1 < 2)",
+        "foo(1, 2)",
+        "round(2.675d, 2) + log(8, 2)",
+        "qty * discount",
+        "1 + 2 * (3 / (4 - 4))",
+        "2 * -(1 < 2) + 1",
+    ];
+
+    #[test]
+    fn test_vm_matches_tree_walker() {
+        let context = Context::new().with("x", 0).with("y", 4).with("qty", 2);
+        let tree_walk = Calculator::new();
+        let bytecode = Calculator::builder().backend(Backend::Bytecode).build();
+        for input in INPUTS {
+            assert_eq!(
+                bytecode.calculate_with(input, &context),
+                tree_walk.calculate_with(input, &context),
+                "{}",
+                input
+            );
+            let compiled = bytecode.compile(input);
+            if let Ok(compiled) = compiled {
+                assert_eq!(
+                    compiled.eval(&context),
+                    tree_walk.calculate_with(input, &context),
+                    "{}",
+                    input
+                );
+            }
+        }
+
+        // Both take a step of fuel for each expression they evaluate, so they
+        // run out at the same expression
+        for fuel in 0..12 {
+            let limits = EvalLimits::new().fuel(fuel);
+            let tree_walk = Calculator::builder().limits(limits).build();
+            let bytecode = Calculator::builder()
+                .backend(Backend::Bytecode)
+                .limits(limits)
+                .build();
+            for input in INPUTS
+                .iter()
+                .chain(&["true and true", "1 < 2 ? 1 : 2", "+-+5"])
+            {
+                assert_eq!(
+                    bytecode.calculate_with(input, &context),
+                    tree_walk.calculate_with(input, &context),
+                    "{} with {} fuel",
+                    input,
+                    fuel
+                );
+            }
+        }
+        let limits = EvalLimits::new().fuel(3);
+        for backend in [Backend::TreeWalk, Backend::Bytecode] {
+            let calculator = Calculator::builder()
+                .backend(backend)
+                .limits(limits)
+                .build();
+            assert_eq!(
+                calculator.calculate("true and true"),
+                Ok(Value::Boolean(true))
+            );
+            assert_eq!(
+                calculator.calculate("1 < 2 ? 1 : 2").unwrap_err().code(),
+                "E0205"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let program = |input| Program::compile(&Parser::new(input).parse().unwrap());
+        let instructions = |input| {
+            program(input)
+                .instructions
+                .into_iter()
+                .map(|(instruction, _)| instruction)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            instructions("1 + 2 * x"),
+            vec![
+                Instruction::Push(Value::Integer(1)),
+                Instruction::Push(Value::Integer(2)),
+                Instruction::Load("x".into()),
+                Instruction::Multiply,
+                Instruction::Add,
+            ]
+        );
+        assert_eq!(
+            instructions("a and b ? f(1, 2) : 3"),
+            vec![
+                Instruction::Load("a".into()),
+                Instruction::Expect("apply and to"),
+                Instruction::JumpIfFalseOrPop(5),
+                Instruction::Load("b".into()),
+                Instruction::Expect("apply and to"),
+                Instruction::Expect("branch on"),
+                Instruction::JumpIfFalse(11),
+                Instruction::Push(Value::Integer(1)),
+                Instruction::Push(Value::Integer(2)),
+                Instruction::Call("f".into(), 2),
+                Instruction::Jump(12),
+                Instruction::Push(Value::Integer(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deep_expression() {
+        let calculator = Calculator::builder()
+            .backend(Backend::Bytecode)
+            .limits(EvalLimits::unlimited())
+            .build();
+        let nested =
+            |open: &str, close: &str| format!("{}1{}", open.repeat(100_000), close.repeat(100_000));
+        let inputs = [
+            (
+                format!("{}1", "1 + ".repeat(100_000)),
+                Value::Integer(100_001),
+            ),
+            (nested("(", ")"), Value::Integer(1)),
+            (nested("-(", ")"), Value::Integer(1)),
+            (nested("abs(1 - ", ")"), Value::Integer(1)),
+            (nested("true ? ", " : 0"), Value::Integer(1)),
+            (nested("1 ^ ", ""), Value::Integer(1)),
+        ];
+        for (input, value) in inputs {
+            let expression = calculator.compile(&input).unwrap();
+            assert_eq!(expression.eval(&Context::new()), Ok(value.clone()));
+            assert_eq!(calculator.calculate(&input), Ok(value));
+        }
+
+        // Deeply nested expressions are dropped without recursing when
+        // parsing fails after them
+        let deep = nested("-(", ")");
+        for input in [
+            format!("{} +", deep),
+            format!("{} $", deep),
+            format!("true ? {} :", deep),
+            format!("max({},", deep),
+        ] {
+            assert!(calculator.calculate(&input).is_err());
+        }
+
+        // The default limits reject such inputs before parsing them
+        let calculator = Calculator::builder().backend(Backend::Bytecode).build();
+        assert_eq!(calculator.calculate(&deep).unwrap_err().code(), "E0200");
+    }
+}